@@ -0,0 +1,240 @@
+use crate::decode::check_allocation;
+use anyhow::{anyhow, bail};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::imageops;
+use image::{
+    AnimationDecoder, Delay, DynamicImage, Frames, ImageDecoder, ImageFormat, ImageReader, Limits,
+};
+use std::io::{BufRead, Seek};
+
+/// Most frames decoded from an animation.
+const MAX_FRAMES: usize = 1000;
+
+/// Most pixels decoded across all frames of an animation, 512 MiB as RGBA.
+const MAX_TOTAL_PIXELS: u64 = 128 * 1024 * 1024;
+
+/// Single frame of an animation, or the whole image for still images.
+pub struct Frame {
+    pub image: DynamicImage,
+    pub delay: Delay,
+}
+
+impl Frame {
+    pub fn still(image: DynamicImage) -> Self {
+        Self {
+            image,
+            delay: Delay::from_numer_denom_ms(0, 1),
+        }
+    }
+}
+
+pub fn supports_animation(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Gif | ImageFormat::WebP | ImageFormat::Png
+    )
+}
+
+/// Decodes every frame of animated GIF, WebP and PNG images, or only the frame at `index` if
+/// given. Other images decode to a single frame.
+pub fn decode_frames<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    index: Option<usize>,
+) -> anyhow::Result<Vec<Frame>> {
+    let frames = match reader.format() {
+        Some(ImageFormat::Gif) => {
+            let mut decoder = GifDecoder::new(reader.into_inner())?;
+            decoder.set_limits(Limits::default())?;
+            decoder.into_frames()
+        }
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::with_limits(reader.into_inner(), Limits::default())?;
+            // Decoding a still image does not check the limits, so check the canvas size up front
+            check_allocation(decoder.total_bytes())?;
+            if !decoder.is_apng()? {
+                return select_still(DynamicImage::from_decoder(decoder)?, index);
+            }
+            decoder.apng()?.into_frames()
+        }
+        Some(ImageFormat::WebP) => {
+            let mut decoder = WebPDecoder::new(reader.into_inner())?;
+            decoder.set_limits(Limits::default())?;
+            // The WebP decoder does not track allocations, so check the canvas size up front
+            check_allocation(decoder.total_bytes())?;
+            if !decoder.has_animation() {
+                return select_still(DynamicImage::from_decoder(decoder)?, index);
+            }
+            decoder.into_frames()
+        }
+        _ => return select_still(reader.decode()?, index),
+    };
+
+    collect_frames(frames, index)
+}
+
+/// Wraps a still image as its only frame, failing if a later frame was requested.
+pub fn select_still(image: DynamicImage, index: Option<usize>) -> anyhow::Result<Vec<Frame>> {
+    if index.is_some_and(|index| index > 0) {
+        bail!("Frame not found");
+    }
+
+    Ok(vec![Frame::still(image)])
+}
+
+/// Collects the frames of an animation, failing if it has more frames or pixels than allowed.
+fn collect_frames(mut frames: Frames, index: Option<usize>) -> anyhow::Result<Vec<Frame>> {
+    let to_frame = |frame: image::Frame| Frame {
+        delay: frame.delay(),
+        image: DynamicImage::ImageRgba8(frame.into_buffer()),
+    };
+
+    if let Some(index) = index {
+        if index >= MAX_FRAMES {
+            bail!("Frame not found");
+        }
+        let frame = frames.nth(index).ok_or(anyhow!("Frame not found"))??;
+        return Ok(vec![Frame::still(to_frame(frame).image)]);
+    }
+
+    let mut collected = Vec::new();
+    let mut total_pixels = 0;
+    for frame in frames {
+        let frame = to_frame(frame?);
+        total_pixels += frame.image.width() as u64 * frame.image.height() as u64;
+        if collected.len() == MAX_FRAMES || total_pixels > MAX_TOTAL_PIXELS {
+            bail!("Animation has too many frames");
+        }
+        collected.push(frame);
+    }
+    if collected.is_empty() {
+        bail!("Image has no frames");
+    }
+
+    Ok(collected)
+}
+
+/// Crops or pads the image from the top-left corner to the given size, filling new areas with
+/// transparency.
+pub fn fit_canvas(image: DynamicImage, width: u32, height: u32) -> DynamicImage {
+    if (image.width(), image.height()) == (width, height) {
+        return image;
+    }
+
+    let mut canvas = DynamicImage::new(width, height, image.color());
+    imageops::replace(&mut canvas, &image, 0, 0);
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{GenericImageView, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    fn create_test_gif() -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let mut encoder = GifEncoder::new(&mut buffer);
+        let frames = (0..3).map(|index| {
+            image::Frame::from_parts(
+                RgbaImage::from_pixel(8, 8, Rgba([index * 100, 0, 0, 255])),
+                0,
+                0,
+                Delay::from_numer_denom_ms(100, 1),
+            )
+        });
+        encoder.encode_frames(frames).unwrap();
+        drop(encoder);
+        buffer
+    }
+
+    fn create_reader(bytes: Vec<u8>) -> ImageReader<Cursor<Vec<u8>>> {
+        ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decodes_all_frames() {
+        let frames = decode_frames(create_reader(create_test_gif()), None).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].image.get_pixel(0, 0), Rgba([200, 0, 0, 255]));
+        assert_eq!(frames[2].delay, Delay::from_numer_denom_ms(100, 1));
+    }
+
+    #[test]
+    fn test_decodes_single_frame() {
+        let frames = decode_frames(create_reader(create_test_gif()), Some(1)).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].image.get_pixel(0, 0), Rgba([100, 0, 0, 255]));
+
+        assert!(decode_frames(create_reader(create_test_gif()), Some(3)).is_err());
+    }
+
+    #[test]
+    fn test_fails_on_too_many_frames() {
+        let mut buffer = Vec::new();
+        let mut encoder = GifEncoder::new(&mut buffer);
+        let frames = (0..=MAX_FRAMES).map(|index| {
+            image::Frame::from_parts(
+                RgbaImage::from_pixel(1, 1, Rgba([index as u8, 0, 0, 255])),
+                0,
+                0,
+                Delay::from_numer_denom_ms(10, 1),
+            )
+        });
+        encoder.encode_frames(frames).unwrap();
+        drop(encoder);
+
+        assert!(decode_frames(create_reader(buffer.clone()), None).is_err());
+        assert!(decode_frames(create_reader(buffer), Some(MAX_FRAMES - 1)).is_ok());
+    }
+
+    #[test]
+    fn test_fails_on_oversized_canvas() {
+        let mut buffer = create_test_gif();
+        // Logical screen of 65535x65535, far above the allocation limit once decoded as RGBA
+        buffer[6..10].copy_from_slice(&[0xff; 4]);
+        assert!(decode_frames(create_reader(buffer), None).is_err());
+    }
+
+    #[test]
+    fn test_fails_on_oversized_png() {
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(RgbaImage::new(1, 1))
+            .write_to(&mut buffer, ImageFormat::Png)
+            .unwrap();
+        let mut buffer = buffer.into_inner();
+        // IHDR of 65535x65535 with its checksum updated, far above the allocation limit
+        buffer[16..24].copy_from_slice(&[0, 0, 0xff, 0xff, 0, 0, 0xff, 0xff]);
+        let checksum = crc32(&buffer[12..29]);
+        buffer[29..33].copy_from_slice(&checksum.to_be_bytes());
+        assert!(decode_frames(create_reader(buffer), None).is_err());
+    }
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in bytes {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn test_fits_canvas() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 12, Rgba([255; 4])));
+        let result = fit_canvas(image, 8, 8);
+        assert_eq!(result.dimensions(), (8, 8));
+        assert_eq!(result.get_pixel(3, 7), Rgba([255; 4]));
+        assert_eq!(result.get_pixel(7, 0), Rgba([0; 4]));
+    }
+}
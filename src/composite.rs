@@ -0,0 +1,296 @@
+use crate::filter::map_pixels_with_position;
+use crate::operation::{Watermark, WatermarkPosition};
+use crate::util::color::{convert_color_type, with_alpha};
+use anyhow::bail;
+use image::imageops::{self, FilterType as ImageFilterType};
+use image::{ColorType, DynamicImage, Rgba, RgbaImage};
+
+/// Most tiles drawn when repeating a watermark.
+const MAX_WATERMARK_TILES: u64 = 10_000;
+
+/// Blends `layer` over `image` at each of the given positions. The result keeps the bit depth
+/// and alpha channel of `image` but always has color, since the layer may add some.
+pub fn composite(
+    image: DynamicImage,
+    layer: &DynamicImage,
+    positions: &[(i64, i64)],
+    opacity: f32,
+) -> DynamicImage {
+    let color = image.color();
+    let mut layer = layer.to_rgba32f();
+    if opacity < 1.0 {
+        for pixel in layer.pixels_mut() {
+            pixel.0[3] *= opacity.max(0.0);
+        }
+    }
+    let layer = DynamicImage::ImageRgba32F(layer);
+
+    let composited = match color.bytes_per_pixel() / color.channel_count() {
+        1 => {
+            let mut canvas = image.into_rgba8();
+            let layer = layer.into_rgba8();
+            for &(x, y) in positions {
+                imageops::overlay(&mut canvas, &layer, x, y);
+            }
+            DynamicImage::ImageRgba8(canvas)
+        }
+        2 => {
+            let mut canvas = image.into_rgba16();
+            let layer = layer.into_rgba16();
+            for &(x, y) in positions {
+                imageops::overlay(&mut canvas, &layer, x, y);
+            }
+            DynamicImage::ImageRgba16(canvas)
+        }
+        _ => {
+            let mut canvas = image.into_rgba32f();
+            let layer = layer.into_rgba32f();
+            for &(x, y) in positions {
+                imageops::overlay(&mut canvas, &layer, x, y);
+            }
+            DynamicImage::ImageRgba32F(canvas)
+        }
+    };
+
+    convert_color_type(composited, composited_color_type(color))
+}
+
+fn composited_color_type(color: ColorType) -> ColorType {
+    match (
+        color.bytes_per_pixel() / color.channel_count(),
+        color.has_alpha(),
+    ) {
+        (1, false) => ColorType::Rgb8,
+        (1, true) => ColorType::Rgba8,
+        (2, false) => ColorType::Rgb16,
+        (2, true) => ColorType::Rgba16,
+        (_, false) => ColorType::Rgb32F,
+        (_, true) => ColorType::Rgba32F,
+    }
+}
+
+/// Draws the watermark onto the image, either once at the gravity position or tiled over the
+/// whole image with the offsets used as spacing between tiles. Fails if tiling would draw more
+/// tiles than allowed.
+pub fn watermark(
+    image: DynamicImage,
+    watermark: &DynamicImage,
+    options: Watermark,
+) -> anyhow::Result<DynamicImage> {
+    let scaled;
+    let watermark = if options.scale > 0.0 {
+        let width = ((image.width() as f32 * options.scale).round() as u32).max(1);
+        let height = (watermark.height() as u64 * width as u64 / watermark.width().max(1) as u64)
+            .clamp(1, u32::MAX as u64) as u32;
+        scaled = watermark.resize_exact(width, height, ImageFilterType::Lanczos3);
+        &scaled
+    } else {
+        watermark
+    };
+
+    let positions = match options.position {
+        WatermarkPosition::Gravity(gravity) => vec![gravity.position(
+            (image.width(), image.height()),
+            (watermark.width(), watermark.height()),
+            (options.x_offset, options.y_offset),
+        )],
+        WatermarkPosition::Repeat => {
+            let step_x = (watermark.width() as u64 + options.x_offset.max(0) as u64).max(1);
+            let step_y = (watermark.height() as u64 + options.y_offset.max(0) as u64).max(1);
+            let tiles =
+                (image.width() as u64).div_ceil(step_x) * (image.height() as u64).div_ceil(step_y);
+            if tiles > MAX_WATERMARK_TILES {
+                bail!("Watermark has too many tiles");
+            }
+
+            (0..image.height() as i64)
+                .step_by(step_y as usize)
+                .flat_map(|y| {
+                    (0..image.width() as i64)
+                        .step_by(step_x as usize)
+                        .map(move |x| (x, y))
+                })
+                .collect()
+        }
+    };
+
+    Ok(composite(image, watermark, &positions, options.opacity))
+}
+
+/// Draws a border of the given width, either around the image by extending the canvas or over
+/// the outermost pixels of the image.
+pub fn border(image: DynamicImage, width: u32, color: Rgba<u8>, inside: bool) -> DynamicImage {
+    if width == 0 {
+        return image;
+    }
+
+    let color_type = composited_color_type(image.color());
+    if inside {
+        let (image_width, image_height) = (image.width(), image.height());
+        let [r, g, b, a] = color.0.map(|channel| channel as f32 / 255.0);
+        return map_pixels_with_position(convert_color_type(image, color_type), |x, y, pixel| {
+            let in_border = x < width
+                || y < width
+                || x >= image_width.saturating_sub(width)
+                || y >= image_height.saturating_sub(width);
+            if !in_border {
+                return pixel;
+            }
+
+            let [pr, pg, pb, pa] = pixel;
+            [
+                r * a + pr * (1.0 - a),
+                g * a + pg * (1.0 - a),
+                b * a + pb * (1.0 - a),
+                a + pa * (1.0 - a),
+            ]
+        });
+    }
+
+    let color_type = if color.0[3] < 255 {
+        with_alpha(color_type)
+    } else {
+        color_type
+    };
+    let mut canvas = convert_color_type(
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            image.width().saturating_add(width.saturating_mul(2)),
+            image.height().saturating_add(width.saturating_mul(2)),
+            color,
+        )),
+        color_type,
+    );
+    imageops::replace(
+        &mut canvas,
+        &convert_color_type(image, color_type),
+        width as i64,
+        width as i64,
+    );
+
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::Gravity;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn create_base_image() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(64, 32, |_, _| Rgb([0, 0, 0])))
+    }
+
+    fn create_watermark() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 8, |_, _| Rgba([255, 255, 255, 255])))
+    }
+
+    #[test]
+    fn test_composites_layer() {
+        let result = composite(create_base_image(), &create_watermark(), &[(4, 4)], 0.5);
+        assert_eq!(result.color(), ColorType::Rgb8);
+
+        let result = result.to_rgb8();
+        assert_eq!(result.get_pixel(0, 0), &Rgb([0, 0, 0]));
+        let [r, _, _] = result.get_pixel(4, 4).0;
+        assert!(r > 120 && r < 135);
+    }
+
+    #[test]
+    fn test_draws_watermark_at_gravity() {
+        let options = Watermark {
+            opacity: 1.0,
+            position: WatermarkPosition::Gravity(Gravity::SouthEast),
+            x_offset: 2,
+            y_offset: 2,
+            scale: 0.0,
+        };
+
+        let result = watermark(create_base_image(), &create_watermark(), options)
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(result.get_pixel(61, 29), &Rgb([255, 255, 255]));
+        assert_eq!(result.get_pixel(62, 30), &Rgb([0, 0, 0]));
+        assert_eq!(result.get_pixel(53, 21), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_draws_scaled_watermark() {
+        let options = Watermark {
+            opacity: 1.0,
+            position: WatermarkPosition::Gravity(Gravity::NorthWest),
+            x_offset: 0,
+            y_offset: 0,
+            scale: 0.5,
+        };
+
+        let result = watermark(create_base_image(), &create_watermark(), options)
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(result.get_pixel(31, 31), &Rgb([255, 255, 255]));
+        assert_eq!(result.get_pixel(32, 0), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_tiles_watermark() {
+        let options = Watermark {
+            opacity: 1.0,
+            position: WatermarkPosition::Repeat,
+            x_offset: 8,
+            y_offset: 8,
+            scale: 0.0,
+        };
+
+        let result = watermark(create_base_image(), &create_watermark(), options)
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(result.get_pixel(0, 0), &Rgb([255, 255, 255]));
+        assert_eq!(result.get_pixel(8, 0), &Rgb([0, 0, 0]));
+        assert_eq!(result.get_pixel(16, 16), &Rgb([255, 255, 255]));
+        assert_eq!(result.get_pixel(48, 16), &Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn test_fails_on_too_many_watermark_tiles() {
+        let base = DynamicImage::ImageRgb8(RgbImage::new(1000, 1000));
+        let tile = DynamicImage::ImageRgba8(RgbaImage::new(1, 1));
+        let options = Watermark {
+            opacity: 1.0,
+            position: WatermarkPosition::Repeat,
+            x_offset: 0,
+            y_offset: 0,
+            scale: 0.001,
+        };
+
+        assert!(watermark(base.clone(), &tile, options).is_err());
+        let options = Watermark {
+            x_offset: 9,
+            y_offset: 9,
+            ..options
+        };
+        assert!(watermark(base, &tile, options).is_ok());
+    }
+
+    #[test]
+    fn test_draws_border_outside() {
+        let result = border(create_base_image(), 4, Rgba([255, 0, 0, 255]), false);
+        assert_eq!((result.width(), result.height()), (72, 40));
+        assert_eq!(result.color(), ColorType::Rgb8);
+
+        let result = result.to_rgb8();
+        assert_eq!(result.get_pixel(0, 0), &Rgb([255, 0, 0]));
+        assert_eq!(result.get_pixel(3, 20), &Rgb([255, 0, 0]));
+        assert_eq!(result.get_pixel(4, 20), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_draws_border_inside() {
+        let result = border(create_base_image(), 4, Rgba([255, 0, 0, 255]), true);
+        assert_eq!((result.width(), result.height()), (64, 32));
+
+        let result = result.to_rgb8();
+        assert_eq!(result.get_pixel(3, 20), &Rgb([255, 0, 0]));
+        assert_eq!(result.get_pixel(60, 20), &Rgb([255, 0, 0]));
+        assert_eq!(result.get_pixel(59, 20), &Rgb([0, 0, 0]));
+        assert_eq!(result.get_pixel(4, 4), &Rgb([0, 0, 0]));
+    }
+}
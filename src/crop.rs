@@ -0,0 +1,426 @@
+use crate::operation::CropGravity;
+use image::imageops::FilterType as ImageFilterType;
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, Rgba};
+
+/// Region to crop, as x, y, width and height.
+type Window = (u32, u32, u32, u32);
+
+/// Crop windows picked from the content of the first frame of an animation, such as trimmed
+/// borders or smart crops, and reused for the other frames so they are all cut the same way.
+#[derive(Default)]
+pub struct CropWindows {
+    windows: Vec<Option<Window>>,
+    next: usize,
+}
+
+impl CropWindows {
+    /// Starts over for the next frame, reusing the windows picked so far.
+    pub fn rewind(&mut self) {
+        self.next = 0;
+    }
+
+    fn pick(&mut self, find: impl FnOnce() -> Option<Window>) -> Option<Window> {
+        let window = match self.windows.get(self.next) {
+            Some(&window) => window,
+            None => {
+                let window = find();
+                self.windows.push(window);
+                window
+            }
+        };
+        self.next += 1;
+        window
+    }
+}
+
+/// Removes borders where every pixel is within `threshold` of `color` on each channel, detecting
+/// the color from the top-left pixel if none is given. Images that are entirely uniform are left
+/// unchanged.
+pub fn trim(
+    image: DynamicImage,
+    threshold: u8,
+    color: Option<Rgba<u8>>,
+    windows: &mut CropWindows,
+) -> DynamicImage {
+    match windows.pick(|| trim_window(&image, threshold, color)) {
+        Some((x, y, width, height)) => image.crop_imm(x, y, width, height),
+        None => image,
+    }
+}
+
+fn trim_window(image: &DynamicImage, threshold: u8, color: Option<Rgba<u8>>) -> Option<Window> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let color = color.unwrap_or_else(|| image.get_pixel(0, 0));
+    let is_border = |x: u32, y: u32| {
+        image
+            .get_pixel(x, y)
+            .0
+            .iter()
+            .zip(color.0)
+            .all(|(&channel, border)| channel.abs_diff(border) <= threshold)
+    };
+    let is_border_row = |y: u32| (0..width).all(|x| is_border(x, y));
+    let is_border_column = |x: u32, top: u32, bottom: u32| (top..bottom).all(|y| is_border(x, y));
+
+    let top = (0..height).find(|&y| !is_border_row(y))?;
+    let bottom = (top..height)
+        .rev()
+        .find(|&y| !is_border_row(y))
+        .unwrap_or(top)
+        + 1;
+    let left = (0..width)
+        .find(|&x| !is_border_column(x, top, bottom))
+        .unwrap_or(0);
+    let right = (left..width)
+        .rev()
+        .find(|&x| !is_border_column(x, top, bottom))
+        .unwrap_or(left)
+        + 1;
+
+    if (left, top, right, bottom) == (0, 0, width, height) {
+        return None;
+    }
+
+    Some((left, top, right - left, bottom - top))
+}
+
+/// Crops a region of the given size, positioned by the gravity. The size is clamped to the image.
+pub fn crop(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    gravity: CropGravity,
+    windows: &mut CropWindows,
+) -> DynamicImage {
+    let width = width.clamp(1, image.width().max(1));
+    let height = height.clamp(1, image.height().max(1));
+    let window = windows.pick(|| {
+        ((width, height) != image.dimensions()).then(|| {
+            let (x, y) = crop_position(&image, width, height, gravity);
+            (x, y, width, height)
+        })
+    });
+
+    match window {
+        Some((x, y, width, height)) => image.crop_imm(x, y, width, height),
+        None => image,
+    }
+}
+
+/// Resizes the image to cover the given size and crops the overflow, positioned by the gravity.
+pub fn fill(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    gravity: CropGravity,
+    windows: &mut CropWindows,
+) -> DynamicImage {
+    let (image_width, image_height) = image.dimensions();
+    if width == 0 || height == 0 || image_width == 0 || image_height == 0 {
+        return image;
+    }
+
+    let scale = f64::max(
+        width as f64 / image_width as f64,
+        height as f64 / image_height as f64,
+    );
+    let resized_width = ((image_width as f64 * scale).round() as u32).max(width);
+    let resized_height = ((image_height as f64 * scale).round() as u32).max(height);
+    let image = image.resize_exact(resized_width, resized_height, ImageFilterType::Lanczos3);
+
+    crop(image, width, height, gravity, windows)
+}
+
+/// Crops the largest region with the given aspect ratio, positioned by the gravity.
+pub fn aspect_ratio(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    gravity: CropGravity,
+    windows: &mut CropWindows,
+) -> DynamicImage {
+    let (image_width, image_height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return image;
+    }
+
+    let ratio = width as f64 / height as f64;
+    let (crop_width, crop_height) = if image_width as f64 / image_height as f64 > ratio {
+        ((image_height as f64 * ratio).round() as u32, image_height)
+    } else {
+        (image_width, (image_width as f64 / ratio).round() as u32)
+    };
+
+    crop(image, crop_width, crop_height, gravity, windows)
+}
+
+fn crop_position(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    gravity: CropGravity,
+) -> (u32, u32) {
+    let (image_width, image_height) = image.dimensions();
+    match gravity {
+        CropGravity::Gravity(gravity) => {
+            let (x, y) = gravity.position((image_width, image_height), (width, height), (0, 0));
+            (
+                x.clamp(0, (image_width - width) as i64) as u32,
+                y.clamp(0, (image_height - height) as i64) as u32,
+            )
+        }
+        CropGravity::Smart => smart_crop_position(image, width, height),
+        CropGravity::FocalPoint(x, y) => {
+            let x = (x * image_width as f32 - width as f32 / 2.0).round() as i64;
+            let y = (y * image_height as f32 - height as f32 / 2.0).round() as i64;
+            (
+                x.clamp(0, (image_width - width) as i64) as u32,
+                y.clamp(0, (image_height - height) as i64) as u32,
+            )
+        }
+    }
+}
+
+/// Finds the crop window with the most detail by repeatedly cutting away whichever edge slice of
+/// the remaining area has the lower edge entropy, first horizontally and then vertically.
+fn smart_crop_position(image: &DynamicImage, width: u32, height: u32) -> (u32, u32) {
+    let edges = detect_edges(&image.to_luma8());
+    let (mut left, mut right) = (0, image.width());
+    let (mut top, mut bottom) = (0, image.height());
+
+    while right - left > width {
+        let excess = right - left - width;
+        let slice = excess.div_ceil(10).max(1).min(excess);
+        let left_entropy = entropy(&edges, left, top, left + slice, bottom);
+        let right_entropy = entropy(&edges, right - slice, top, right, bottom);
+        if left_entropy < right_entropy {
+            left += slice;
+        } else {
+            right -= slice;
+        }
+    }
+
+    while bottom - top > height {
+        let excess = bottom - top - height;
+        let slice = excess.div_ceil(10).max(1).min(excess);
+        let top_entropy = entropy(&edges, left, top, right, top + slice);
+        let bottom_entropy = entropy(&edges, left, bottom - slice, right, bottom);
+        if top_entropy < bottom_entropy {
+            top += slice;
+        } else {
+            bottom -= slice;
+        }
+    }
+
+    (left, top)
+}
+
+/// Approximates the gradient magnitude of each pixel with central differences.
+fn detect_edges(image: &GrayImage) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let value = |x: u32, y: u32| image.get_pixel(x, y).0[0] as i32;
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let dx = value((x + 1).min(width - 1), y) - value(x.saturating_sub(1), y);
+        let dy = value(x, (y + 1).min(height - 1)) - value(x, y.saturating_sub(1));
+        Luma([(dx.abs() + dy.abs()).min(255) as u8])
+    })
+}
+
+/// Shannon entropy of the pixel value histogram within the region.
+fn entropy(image: &GrayImage, left: u32, top: u32, right: u32, bottom: u32) -> f64 {
+    let mut histogram = [0u32; 256];
+    for y in top..bottom {
+        for x in left..right {
+            histogram[image.get_pixel(x, y).0[0] as usize] += 1;
+        }
+    }
+
+    let total = ((right - left) * (bottom - top)) as f64;
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let probability = count as f64 / total;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::Gravity;
+    use image::{Rgb, RgbImage, RgbaImage};
+
+    fn create_test_image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 24, |x, y| {
+            if (4..20).contains(&x) && (6..18).contains(&y) {
+                Rgba([0, 0, 0, 255])
+            } else if x == 31 {
+                Rgba([250, 250, 250, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        }))
+    }
+
+    #[test]
+    fn test_trims_detected_border() {
+        let result = trim(create_test_image(), 10, None, &mut CropWindows::default());
+        assert_eq!((result.width(), result.height()), (16, 12));
+        assert_eq!(result.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_respects_threshold() {
+        let result = trim(create_test_image(), 0, None, &mut CropWindows::default());
+        assert_eq!((result.width(), result.height()), (28, 24));
+    }
+
+    #[test]
+    fn test_trims_given_color() {
+        let result = trim(
+            create_test_image(),
+            0,
+            Some(Rgba([0, 0, 0, 255])),
+            &mut CropWindows::default(),
+        );
+        assert_eq!((result.width(), result.height()), (32, 24));
+    }
+
+    #[test]
+    fn test_keeps_uniform_image() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([9, 9, 9, 255])));
+        let result = trim(image, 0, None, &mut CropWindows::default());
+        assert_eq!((result.width(), result.height()), (8, 8));
+    }
+
+    /// Flat gray image with a noisy square, which should attract the smart crop.
+    fn create_detailed_image(width: u32, height: u32, square: (u32, u32)) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            if (square.0..square.0 + 16).contains(&x) && (square.1..square.1 + 16).contains(&y) {
+                let value = ((x * 7919 + y * 104729) % 251) as u8;
+                Rgb([value, value / 2, 255 - value])
+            } else {
+                Rgb([128, 128, 128])
+            }
+        }))
+    }
+
+    #[test]
+    fn test_crops_at_gravity() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(40, 20, |x, _| Rgb([x as u8, 0, 0])));
+
+        let result = crop(
+            image.clone(),
+            10,
+            10,
+            CropGravity::Gravity(Gravity::East),
+            &mut CropWindows::default(),
+        );
+        assert_eq!((result.width(), result.height()), (10, 10));
+        assert_eq!(result.get_pixel(0, 0), Rgba([30, 0, 0, 255]));
+
+        let result = crop(
+            image,
+            100,
+            10,
+            CropGravity::Gravity(Gravity::Center),
+            &mut CropWindows::default(),
+        );
+        assert_eq!((result.width(), result.height()), (40, 10));
+    }
+
+    #[test]
+    fn test_crops_around_focal_point() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(40, 20, |x, _| Rgb([x as u8, 0, 0])));
+
+        let result = crop(
+            image.clone(),
+            10,
+            10,
+            CropGravity::FocalPoint(0.5, 0.5),
+            &mut CropWindows::default(),
+        );
+        assert_eq!(result.get_pixel(0, 0), Rgba([15, 0, 0, 255]));
+
+        let result = crop(
+            image,
+            10,
+            10,
+            CropGravity::FocalPoint(1.0, 0.0),
+            &mut CropWindows::default(),
+        );
+        assert_eq!(result.get_pixel(0, 0), Rgba([30, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_crops_to_aspect_ratio() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(160, 160));
+        let result = aspect_ratio(
+            image,
+            16,
+            9,
+            CropGravity::Gravity(Gravity::Center),
+            &mut CropWindows::default(),
+        );
+        assert_eq!((result.width(), result.height()), (160, 90));
+
+        let image = DynamicImage::ImageRgb8(RgbImage::new(160, 90));
+        let result = aspect_ratio(
+            image,
+            1,
+            2,
+            CropGravity::Gravity(Gravity::Center),
+            &mut CropWindows::default(),
+        );
+        assert_eq!((result.width(), result.height()), (45, 90));
+    }
+
+    #[test]
+    fn test_fills() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(40, 20));
+        let result = fill(
+            image,
+            10,
+            10,
+            CropGravity::Gravity(Gravity::Center),
+            &mut CropWindows::default(),
+        );
+        assert_eq!((result.width(), result.height()), (10, 10));
+    }
+
+    #[test]
+    fn test_smart_crops_horizontally() {
+        let image = create_detailed_image(96, 32, (72, 8));
+        let (x, y) = smart_crop_position(&image, 32, 32);
+        assert!((56..=72).contains(&x));
+        assert_eq!(y, 0);
+
+        let image = create_detailed_image(96, 32, (4, 8));
+        let (x, _) = smart_crop_position(&image, 32, 32);
+        assert!(x <= 4);
+    }
+
+    #[test]
+    fn test_smart_crops_vertically() {
+        let image = create_detailed_image(32, 96, (8, 40));
+        let (x, y) = smart_crop_position(&image, 32, 32);
+        assert_eq!(x, 0);
+        assert!(y <= 40 && y + 32 >= 56);
+    }
+
+    #[test]
+    fn test_smart_crop_is_deterministic() {
+        let image = create_detailed_image(128, 128, (90, 20));
+        let first = smart_crop_position(&image, 48, 48);
+        assert_eq!(first, smart_crop_position(&image, 48, 48));
+        assert!(first.0 <= 90 && first.0 + 48 >= 106);
+        assert!(first.1 <= 20 && first.1 + 48 >= 36);
+    }
+}
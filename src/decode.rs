@@ -0,0 +1,76 @@
+use anyhow::{anyhow, bail};
+use image::{DynamicImage, ImageDecoder, Limits, RgbImage};
+use jxl_oxide::integration::JxlDecoder;
+use std::io::Cursor;
+
+/// Fails if decoding would allocate more than the default limit of `image`, for decoders that do
+/// not check it themselves.
+pub fn check_allocation(bytes: u64) -> anyhow::Result<()> {
+    if Limits::default()
+        .max_alloc
+        .is_some_and(|max_alloc| bytes > max_alloc)
+    {
+        bail!("Image is too large");
+    }
+
+    Ok(())
+}
+
+/// Decodes the first frame of a JPEG XL image.
+pub fn decode_jxl(bytes: &[u8]) -> anyhow::Result<DynamicImage> {
+    let mut decoder = JxlDecoder::new(Cursor::new(bytes))?;
+    decoder.set_limits(Limits::default())?;
+    // The JPEG XL decoder does not track allocations, so check the canvas size up front
+    check_allocation(decoder.total_bytes())?;
+    Ok(DynamicImage::from_decoder(decoder)?)
+}
+
+/// Decodes a PCX image, which `image` has no decoder for.
+pub fn decode_pcx(bytes: &[u8]) -> anyhow::Result<DynamicImage> {
+    let mut reader = pcx::Reader::new(Cursor::new(bytes))?;
+    let (width, height) = (reader.width() as u32, reader.height() as u32);
+    check_allocation(width as u64 * height as u64 * 3)?;
+
+    let mut pixels = vec![0; width as usize * height as usize * 3];
+    reader.read_rgb_pixels(&mut pixels)?;
+    RgbImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or(anyhow!("Invalid PCX image"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_decodes_jxl() {
+        let image = decode_jxl(include_bytes!("../tests/fixtures/alpha.jxl")).unwrap();
+        assert_eq!(image.dimensions(), (12, 8));
+        assert!(image.color().has_alpha());
+        assert_eq!(image.get_pixel(0, 0), image::Rgba([0, 200, 0, 255]));
+        assert_eq!(image.get_pixel(11, 7), image::Rgba([0, 200, 0, 0]));
+        assert!(decode_jxl(b"not a jxl").is_err());
+    }
+
+    #[test]
+    fn test_decodes_pcx() {
+        let mut bytes = Vec::new();
+        let mut writer = pcx::WriterRgb::new(&mut bytes, (4, 2), (300, 300)).unwrap();
+        for _ in 0..2 {
+            writer.write_row(&[10, 20, 30].repeat(4)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let image = decode_pcx(&bytes).unwrap();
+        assert_eq!(image.dimensions(), (4, 2));
+        assert_eq!(image.get_pixel(3, 1), image::Rgba([10, 20, 30, 255]));
+        assert!(decode_pcx(b"not a pcx").is_err());
+    }
+
+    #[test]
+    fn test_checks_allocation() {
+        assert!(check_allocation(1024).is_ok());
+        assert!(check_allocation(u64::MAX).is_err());
+    }
+}
@@ -1,63 +1,209 @@
-use anyhow::bail;
+use crate::animation::{Frame, supports_animation};
+use crate::quantize::quantize;
+use crate::util::color::convert_color_type;
+use anyhow::{anyhow, bail};
 use image::codecs::avif::AvifEncoder;
 use image::codecs::bmp::BmpEncoder;
 use image::codecs::farbfeld::FarbfeldEncoder;
-use image::codecs::gif::GifEncoder;
+use image::codecs::gif::{GifEncoder, Repeat};
 use image::codecs::hdr::HdrEncoder;
 use image::codecs::ico::IcoEncoder;
-use image::codecs::jpeg::JpegEncoder;
 use image::codecs::openexr::OpenExrEncoder;
-use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
 use image::codecs::pnm::PnmEncoder;
 use image::codecs::qoi::QoiEncoder;
 use image::codecs::tga::TgaEncoder;
 use image::codecs::tiff::TiffEncoder;
 use image::codecs::webp::WebPEncoder;
-use image::{DynamicImage, ImageEncoder, ImageFormat};
+use image::error::{EncodingError, ImageFormatHint};
+use image::{
+    ColorType, Delay, DynamicImage, ImageBuffer, ImageEncoder, ImageError, ImageFormat,
+    ImageResult, Rgb, RgbaImage,
+};
 
-use std::io::Cursor;
+use std::io::{Cursor, Write};
 
+#[derive(Clone, Copy)]
 pub struct EncodeOptions {
     pub format: ImageFormat,
     pub speed: Option<u8>,
     pub quality: Option<u8>,
+    pub background: Option<Rgb<u8>>,
+    pub lossless: bool,
+    pub progressive: bool,
+    pub subsampling: Option<Subsampling>,
+    pub compression: Option<u8>,
+    pub colors: Option<u16>,
+    pub max_bytes: Option<usize>,
+}
+
+/// Chroma subsampling ratio used for JPEG output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subsampling {
+    Yuv444,
+    Yuv422,
+    Yuv420,
+}
+
+impl EncodeOptions {
+    pub fn new(format: ImageFormat) -> Self {
+        Self {
+            format,
+            speed: None,
+            quality: None,
+            background: None,
+            lossless: false,
+            progressive: false,
+            subsampling: None,
+            compression: None,
+            colors: None,
+            max_bytes: None,
+        }
+    }
 }
 
 pub fn encode_image(
     image: DynamicImage,
     options: EncodeOptions,
 ) -> anyhow::Result<(Cursor<Vec<u8>>, ImageFormat)> {
+    let image = convert_for_format(
+        image,
+        options.format,
+        options.background.unwrap_or(Rgb([255, 255, 255])),
+    )?;
+
+    let buffer = match options.max_bytes {
+        Some(max_bytes) if uses_quality(&options) => {
+            encode_within_size(&image, options, max_bytes)?
+        }
+        _ => encode(&image, &options)?,
+    };
+
+    Ok((buffer, options.format))
+}
+
+/// Encodes the frames as an animation if there are several and the output format supports it,
+/// otherwise encodes only the first frame.
+pub fn encode_frames(
+    frames: Vec<Frame>,
+    options: EncodeOptions,
+) -> anyhow::Result<(Cursor<Vec<u8>>, ImageFormat)> {
+    if frames.len() == 1 || !supports_animation(options.format) {
+        let Some(frame) = frames.into_iter().next() else {
+            bail!("Image has no frames");
+        };
+        return encode_image(frame.image, options);
+    }
+
+    let mut buffer = Cursor::new(Vec::new());
+    let result = match options.format {
+        ImageFormat::WebP => encode_animated_webp(&mut buffer, frames, &options),
+        ImageFormat::Png => {
+            let delays: Vec<_> = frames.iter().map(|frame| frame.delay).collect();
+            let images: Vec<_> = frames
+                .into_iter()
+                .map(|frame| DynamicImage::ImageRgba8(frame.image.into_rgba8()))
+                .collect();
+            encode_png(
+                &mut buffer,
+                &images,
+                &delays,
+                options.compression.unwrap_or(6),
+                options.colors,
+            )
+        }
+        _ => {
+            let mut encoder = GifEncoder::new_with_speed(&mut buffer, gif_speed(&options));
+            encoder.set_repeat(Repeat::Infinite).and_then(|_| {
+                encoder.encode_frames(frames.into_iter().map(|frame| {
+                    image::Frame::from_parts(frame.image.into_rgba8(), 0, 0, frame.delay)
+                }))
+            })
+        }
+    };
+    if result.is_err() {
+        bail!("Encoding image failed");
+    };
+
+    Ok((buffer, options.format))
+}
+
+/// GIF encoding speed, which the encoder only accepts from 1 to 30.
+fn gif_speed(options: &EncodeOptions) -> i32 {
+    options.speed.unwrap_or(10).clamp(1, 30) as i32
+}
+
+/// Whether the output format is encoded lossily with the `quality` option.
+fn uses_quality(options: &EncodeOptions) -> bool {
+    match options.format {
+        ImageFormat::Jpeg | ImageFormat::Avif => true,
+        ImageFormat::WebP => !options.lossless,
+        _ => false,
+    }
+}
+
+/// Binary searches for the highest quality, up to the requested one, whose output fits within
+/// `max_bytes`. Falls back to the lowest quality if nothing fits.
+fn encode_within_size(
+    image: &DynamicImage,
+    options: EncodeOptions,
+    max_bytes: usize,
+) -> anyhow::Result<Cursor<Vec<u8>>> {
+    let (mut low, mut high) = (1, options.quality.unwrap_or(100).clamp(1, 100));
+    let mut result = None;
+
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let buffer = encode(
+            image,
+            &EncodeOptions {
+                quality: Some(quality),
+                ..options
+            },
+        )?;
+
+        let fits = buffer.get_ref().len() <= max_bytes;
+        if fits || quality == 1 {
+            result = Some(buffer);
+        }
+        if fits {
+            low = quality + 1;
+        } else {
+            high = quality - 1;
+        }
+    }
+
+    result.ok_or(anyhow!("Encoding image failed"))
+}
+
+fn encode(image: &DynamicImage, options: &EncodeOptions) -> anyhow::Result<Cursor<Vec<u8>>> {
     let mut buffer = Cursor::new(Vec::new());
 
     let result = match options.format {
-        ImageFormat::Png => PngEncoder::new_with_quality(
+        ImageFormat::Png => encode_png(
             &mut buffer,
-            CompressionType::Default,
-            PngFilterType::Adaptive,
-        )
-        .write_image(
+            std::slice::from_ref(image),
+            &[],
+            options.compression.unwrap_or(6),
+            options.colors,
+        ),
+
+        ImageFormat::Jpeg => encode_jpeg(
+            &mut buffer,
+            image,
+            options.quality.unwrap_or(80),
+            options.progressive,
+            options.subsampling.unwrap_or(Subsampling::Yuv444),
+        ),
+
+        ImageFormat::Gif => GifEncoder::new_with_speed(&mut buffer, gif_speed(options)).encode(
             image.as_bytes(),
             image.width(),
             image.height(),
             image.color().into(),
         ),
 
-        ImageFormat::Jpeg => {
-            JpegEncoder::new_with_quality(&mut buffer, options.quality.unwrap_or(80)).write_image(
-                image.as_bytes(),
-                image.width(),
-                image.height(),
-                image.color().into(),
-            )
-        }
-
-        ImageFormat::Gif => {
-            GifEncoder::new_with_speed(&mut buffer, options.speed.unwrap_or(10) as i32).encode(
-                image.as_bytes(),
-                image.width(),
-                image.height(),
-                image.color().into(),
-            )
+        ImageFormat::WebP if !options.lossless => {
+            encode_lossy_webp(&mut buffer, image, webp_quality(options))
         }
 
         ImageFormat::WebP => WebPEncoder::new_lossless(&mut buffer).write_image(
@@ -150,13 +296,369 @@ pub fn encode_image(
         bail!("Encoding image failed");
     };
 
-    Ok((buffer, options.format))
+    Ok(buffer)
+}
+
+/// Encodes a PNG with a zlib compression level (0-9), reducing it to an indexed palette of at
+/// most `colors` colors if given. Several images are written as an APNG animation, which
+/// requires them to share the same size and color type.
+fn encode_png(
+    buffer: &mut Cursor<Vec<u8>>,
+    images: &[DynamicImage],
+    delays: &[Delay],
+    compression: u8,
+    colors: Option<u16>,
+) -> ImageResult<()> {
+    let encoding_error = |error: png::EncodingError| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Png),
+            error,
+        ))
+    };
+    let Some(first) = images.first() else {
+        return Err(ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Png),
+            "Image has no frames",
+        )));
+    };
+    let (width, height) = (first.width(), first.height());
+
+    let mut encoder = png::Encoder::new(buffer, width, height);
+    encoder.set_deflate_compression(match compression {
+        0 => png::DeflateCompression::NoCompression,
+        level => png::DeflateCompression::Level(level),
+    });
+    encoder.set_filter(png::Filter::Adaptive);
+    if images.len() > 1 {
+        encoder
+            .set_animated(images.len() as u32, 0)
+            .map_err(encoding_error)?;
+    }
+
+    let data: Vec<Vec<u8>> = if let Some(colors) = colors {
+        // Frames share a single palette, so they are quantized together as one tall image
+        let pixels = images
+            .iter()
+            .flat_map(|image| image.to_rgba8().into_raw())
+            .collect();
+        let stacked = u32::try_from(images.len())
+            .ok()
+            .and_then(|count| height.checked_mul(count))
+            .and_then(|stacked_height| RgbaImage::from_raw(width, stacked_height, pixels))
+            .ok_or_else(|| {
+                ImageError::Encoding(EncodingError::new(
+                    ImageFormatHint::Exact(ImageFormat::Png),
+                    "Frames do not fit in a single image",
+                ))
+            })?;
+        let indexed = quantize(&stacked, colors);
+        let (palette, trns): (Vec<_>, Vec<_>) = indexed
+            .palette
+            .chunks_exact(4)
+            .map(|color| ([color[0], color[1], color[2]], color[3]))
+            .unzip();
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(palette.concat());
+        if first.color().has_alpha() {
+            encoder.set_trns(trns);
+        }
+        indexed
+            .indices
+            .chunks_exact(width as usize * height as usize)
+            .map(<[u8]>::to_vec)
+            .collect()
+    } else {
+        let color = first.color();
+        encoder.set_color(match (color.has_color(), color.has_alpha()) {
+            (false, false) => png::ColorType::Grayscale,
+            (false, true) => png::ColorType::GrayscaleAlpha,
+            (true, false) => png::ColorType::Rgb,
+            (true, true) => png::ColorType::Rgba,
+        });
+        if color.bytes_per_pixel() / color.channel_count() == 2 {
+            // PNG stores 16-bit samples in big-endian order
+            encoder.set_depth(png::BitDepth::Sixteen);
+            images
+                .iter()
+                .map(|image| {
+                    image
+                        .as_bytes()
+                        .chunks_exact(2)
+                        .flat_map(|sample| u16::from_ne_bytes([sample[0], sample[1]]).to_be_bytes())
+                        .collect()
+                })
+                .collect()
+        } else {
+            encoder.set_depth(png::BitDepth::Eight);
+            images
+                .iter()
+                .map(|image| image.as_bytes().to_vec())
+                .collect()
+        }
+    };
+
+    let mut writer = encoder.write_header().map_err(encoding_error)?;
+    for (index, data) in data.iter().enumerate() {
+        if let Some(delay) = delays.get(index) {
+            let (numerator, denominator) = delay.numer_denom_ms();
+            let (numerator, denominator) = reduce_delay(numerator, denominator);
+            writer
+                .set_frame_delay(numerator, denominator)
+                .map_err(encoding_error)?;
+        }
+        writer.write_image_data(data).map_err(encoding_error)?;
+    }
+    writer.finish().map_err(encoding_error)
+}
+
+/// Converts a delay in milliseconds to an APNG delay in seconds, which has 16-bit parts.
+fn reduce_delay(numerator: u32, denominator: u32) -> (u16, u16) {
+    let milliseconds = (numerator / denominator.max(1)).min(u16::MAX as u32);
+    (milliseconds as u16, 1000)
+}
+
+fn encode_jpeg(
+    buffer: &mut Cursor<Vec<u8>>,
+    image: &DynamicImage,
+    quality: u8,
+    progressive: bool,
+    subsampling: Subsampling,
+) -> ImageResult<()> {
+    let encoding_error = |error: String| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Jpeg),
+            error,
+        ))
+    };
+    let (Ok(width), Ok(height)) = (u16::try_from(image.width()), u16::try_from(image.height()))
+    else {
+        return Err(encoding_error("Image is too large for JPEG".to_string()));
+    };
+    let color = if image.color().has_color() {
+        jpeg_encoder::ColorType::Rgb
+    } else {
+        jpeg_encoder::ColorType::Luma
+    };
+
+    let mut encoder = jpeg_encoder::Encoder::new(buffer, quality);
+    encoder.set_progressive(progressive);
+    encoder.set_sampling_factor(match subsampling {
+        Subsampling::Yuv444 => jpeg_encoder::SamplingFactor::R_4_4_4,
+        Subsampling::Yuv422 => jpeg_encoder::SamplingFactor::R_4_2_2,
+        Subsampling::Yuv420 => jpeg_encoder::SamplingFactor::R_4_2_0,
+    });
+    encoder
+        .encode(image.as_bytes(), width, height, color)
+        .map_err(|error| encoding_error(error.to_string()))
+}
+
+/// WebP quality, which libwebp only accepts from 0 to 100.
+fn webp_quality(options: &EncodeOptions) -> f32 {
+    options.quality.unwrap_or(80).min(100) as f32
+}
+
+fn encode_lossy_webp(
+    buffer: &mut Cursor<Vec<u8>>,
+    image: &DynamicImage,
+    quality: f32,
+) -> ImageResult<()> {
+    let encoder = if image.color().has_alpha() {
+        webp::Encoder::from_rgba(image.as_bytes(), image.width(), image.height())
+    } else {
+        webp::Encoder::from_rgb(image.as_bytes(), image.width(), image.height())
+    };
+    let memory = encoder.encode_simple(false, quality).map_err(|error| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::WebP),
+            format!("{error:?}"),
+        ))
+    })?;
+
+    buffer.write_all(&memory).map_err(ImageError::IoError)
+}
+
+fn encode_animated_webp(
+    buffer: &mut Cursor<Vec<u8>>,
+    frames: Vec<Frame>,
+    options: &EncodeOptions,
+) -> ImageResult<()> {
+    let encoding_error = |error: String| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::WebP),
+            error,
+        ))
+    };
+
+    let mut config = webp::WebPConfig::new()
+        .map_err(|_| encoding_error("Invalid WebP configuration".to_string()))?;
+    config.lossless = options.lossless as i32;
+    config.alpha_compression = !options.lossless as i32;
+    config.quality = webp_quality(options);
+
+    let (width, height) = (frames[0].image.width(), frames[0].image.height());
+    let images: Vec<_> = frames.iter().map(|frame| frame.image.to_rgba8()).collect();
+    let mut encoder = webp::AnimEncoder::new(width, height, &config);
+    encoder.set_loop_count(0);
+
+    // Frames are placed by their start time in milliseconds
+    let mut timestamp = 0;
+    for (frame, image) in frames.iter().zip(&images) {
+        encoder.add_frame(webp::AnimFrame::from_rgba(image, width, height, timestamp));
+        let (numerator, denominator) = frame.delay.numer_denom_ms();
+        timestamp += (numerator / denominator.max(1)) as i32;
+    }
+
+    let memory = encoder
+        .try_encode()
+        .map_err(|error| encoding_error(format!("{error:?}")))?;
+    buffer.write_all(&memory).map_err(ImageError::IoError)
+}
+
+pub fn supports_alpha(format: ImageFormat) -> bool {
+    supported_color_types(format)
+        .iter()
+        .any(|color| color.has_alpha())
+}
+
+/// Color types accepted by the encoder of each output format.
+fn supported_color_types(format: ImageFormat) -> &'static [ColorType] {
+    use ColorType::*;
+
+    match format {
+        ImageFormat::Png | ImageFormat::Ico | ImageFormat::Avif => {
+            &[L8, La8, Rgb8, Rgba8, L16, La16, Rgb16, Rgba16]
+        }
+        ImageFormat::Jpeg => &[L8, Rgb8],
+        ImageFormat::Gif | ImageFormat::Qoi | ImageFormat::WebP => &[Rgb8, Rgba8],
+        ImageFormat::Pnm | ImageFormat::Tga | ImageFormat::Bmp => &[L8, La8, Rgb8, Rgba8],
+        ImageFormat::Tiff => &[L8, Rgb8, Rgba8, L16, Rgb16, Rgba16],
+        ImageFormat::Hdr => &[Rgb32F],
+        ImageFormat::OpenExr => &[Rgb32F, Rgba32F],
+        ImageFormat::Farbfeld => &[Rgba16],
+        _ => &[],
+    }
+}
+
+/// Converts the image to the supported color type closest to its current one, flattening
+/// transparency onto the background color if the format has no alpha channel.
+fn convert_for_format(
+    image: DynamicImage,
+    format: ImageFormat,
+    background: Rgb<u8>,
+) -> anyhow::Result<DynamicImage> {
+    let supported = supported_color_types(format);
+    if supported.contains(&image.color()) {
+        return Ok(image);
+    }
+
+    let image = if image.color().has_alpha() && !supports_alpha(format) {
+        flatten(image, background)
+    } else {
+        image
+    };
+
+    let source = image.color();
+    let Some(&target) = supported
+        .iter()
+        .min_by_key(|&&target| conversion_cost(source, target))
+    else {
+        bail!("Unsupported output format");
+    };
+
+    Ok(convert_color_type(image, target))
+}
+
+/// Penalizes conversions that lose information (alpha, color, precision) much more than ones
+/// that only add to it.
+fn conversion_cost(source: ColorType, target: ColorType) -> u32 {
+    let depth = |color: ColorType| u32::from(color.bytes_per_pixel() / color.channel_count());
+
+    let alpha_cost = match (source.has_alpha(), target.has_alpha()) {
+        (true, false) => 100,
+        (false, true) => 1,
+        _ => 0,
+    };
+    let color_cost = match (source.has_color(), target.has_color()) {
+        (true, false) => 100,
+        (false, true) => 2,
+        _ => 0,
+    };
+    let depth_cost = match depth(source).cmp(&depth(target)) {
+        std::cmp::Ordering::Greater => 20 * (depth(source) - depth(target)),
+        std::cmp::Ordering::Less => 4 * (depth(target) - depth(source)),
+        std::cmp::Ordering::Equal => 0,
+    };
+
+    alpha_cost + color_cost + depth_cost
+}
+
+/// Composites the image onto a solid background color, dropping the alpha channel but keeping
+/// the original bit depth.
+fn flatten(image: DynamicImage, background: Rgb<u8>) -> DynamicImage {
+    let background = background.0.map(|channel| channel as f32 / 255.0);
+    let source = image.to_rgba32f();
+    let flattened = ImageBuffer::from_fn(source.width(), source.height(), |x, y| {
+        let [r, g, b, a] = source.get_pixel(x, y).0;
+        Rgb([
+            r * a + background[0] * (1.0 - a),
+            g * a + background[1] * (1.0 - a),
+            b * a + background[2] * (1.0 - a),
+        ])
+    });
+    let flattened = DynamicImage::ImageRgb32F(flattened);
+
+    match image {
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgba8(_) => {
+            DynamicImage::ImageRgb8(flattened.into_rgb8())
+        }
+        DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgba16(_) => {
+            DynamicImage::ImageRgb16(flattened.into_rgb16())
+        }
+        _ => flattened,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use image::{DynamicImage, ImageFormat, RgbaImage};
+    use image::{DynamicImage, GenericImageView, ImageFormat, RgbImage, Rgba, RgbaImage};
+
+    const OUTPUT_FORMATS: [ImageFormat; 14] = [
+        ImageFormat::Png,
+        ImageFormat::Jpeg,
+        ImageFormat::Gif,
+        ImageFormat::WebP,
+        ImageFormat::Pnm,
+        ImageFormat::Tiff,
+        ImageFormat::Tga,
+        ImageFormat::Bmp,
+        ImageFormat::Ico,
+        ImageFormat::Hdr,
+        ImageFormat::OpenExr,
+        ImageFormat::Farbfeld,
+        ImageFormat::Avif,
+        ImageFormat::Qoi,
+    ];
+
+    fn create_test_images() -> Vec<DynamicImage> {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 8, |x, y| {
+            Rgba([(x * 32) as u8, (y * 32) as u8, 128, 192])
+        }));
+
+        vec![
+            DynamicImage::ImageLuma8(image.to_luma8()),
+            DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+            DynamicImage::ImageRgb8(image.to_rgb8()),
+            DynamicImage::ImageLuma16(image.to_luma16()),
+            DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+            DynamicImage::ImageRgb16(image.to_rgb16()),
+            DynamicImage::ImageRgba16(image.to_rgba16()),
+            DynamicImage::ImageRgb32F(image.to_rgb32f()),
+            DynamicImage::ImageRgba32F(image.to_rgba32f()),
+            image,
+        ]
+    }
 
     #[test]
     fn test_encodes_image() {
@@ -164,9 +666,9 @@ mod tests {
             image::Rgba([255, 255, 255, 255])
         }));
         let options = EncodeOptions {
-            format: ImageFormat::Avif,
             speed: Some(4),
             quality: Some(60),
+            ..EncodeOptions::new(ImageFormat::Avif)
         };
 
         let result = encode_image(image, options);
@@ -175,4 +677,315 @@ mod tests {
         assert!(!buffer.get_ref().is_empty());
         assert_eq!(format, ImageFormat::Avif);
     }
+
+    #[test]
+    fn test_flattens_transparent_image_for_jpeg() {
+        let image =
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 16, |_, _| image::Rgba([0, 0, 0, 0])));
+        let options = EncodeOptions {
+            background: Some(Rgb([255, 0, 0])),
+            ..EncodeOptions::new(ImageFormat::Jpeg)
+        };
+
+        let (buffer, _) = encode_image(image, options).unwrap();
+        let decoded = image::load_from_memory(buffer.get_ref()).unwrap().to_rgb8();
+        let [r, g, b] = decoded.get_pixel(8, 8).0;
+        assert!(r > 240 && g < 16 && b < 16);
+    }
+
+    #[test]
+    fn test_encodes_progressive_subsampled_jpeg() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(32, 32, |x, y| {
+            Rgb([(x * 8) as u8, (y * 8) as u8, 128])
+        }));
+        let options = EncodeOptions {
+            progressive: true,
+            subsampling: Some(Subsampling::Yuv420),
+            ..EncodeOptions::new(ImageFormat::Jpeg)
+        };
+
+        let (buffer, _) = encode_image(image, options).unwrap();
+        let bytes = buffer.get_ref();
+        // SOF2 marks a progressive JPEG, followed by the luma sampling factors
+        let sof = bytes
+            .windows(2)
+            .position(|marker| marker == [0xFF, 0xC2])
+            .unwrap();
+        assert_eq!(bytes[sof + 11], 0x22);
+        assert_eq!(
+            image::load_from_memory(bytes).unwrap().dimensions(),
+            (32, 32)
+        );
+    }
+
+    #[test]
+    fn test_encodes_quantized_png() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(32, 32, |x, y| {
+            Rgb([(x * 8) as u8, (y * 8) as u8, 128])
+        }));
+        let options = EncodeOptions {
+            compression: Some(9),
+            colors: Some(16),
+            ..EncodeOptions::new(ImageFormat::Png)
+        };
+
+        let (buffer, _) = encode_image(image, options).unwrap();
+        let decoder = png::Decoder::new(Cursor::new(buffer.get_ref().as_slice()));
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.color_type, png::ColorType::Indexed);
+        assert_eq!(info.palette.as_ref().unwrap().len(), 16 * 3);
+        assert!(info.trns.is_none());
+    }
+
+    #[test]
+    fn test_round_trips_16_bit_png() {
+        let image = DynamicImage::ImageRgba16(ImageBuffer::from_fn(8, 8, |x, y| {
+            Rgba([x as u16 * 4000 + 1, y as u16 * 4000 + 2, 300, 65535])
+        }));
+        let options = EncodeOptions::new(ImageFormat::Png);
+
+        let (buffer, _) = encode_image(image.clone(), options).unwrap();
+        let decoded = image::load_from_memory(buffer.get_ref()).unwrap();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_encodes_within_byte_budget() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(128, 128, |x, y| {
+            let value = ((x * 7919 + y * 104729) % 251) as u8;
+            Rgb([value, value / 2, 255 - value])
+        }));
+        let encoded_size = |quality: u8| {
+            let options = EncodeOptions {
+                quality: Some(quality),
+                ..EncodeOptions::new(ImageFormat::Jpeg)
+            };
+            encode_image(image.clone(), options)
+                .unwrap()
+                .0
+                .into_inner()
+                .len()
+        };
+        let max_bytes = (encoded_size(30) + encoded_size(90)) / 2;
+
+        let options = EncodeOptions {
+            max_bytes: Some(max_bytes),
+            ..EncodeOptions::new(ImageFormat::Jpeg)
+        };
+        let (buffer, _) = encode_image(image.clone(), options).unwrap();
+        assert!(buffer.get_ref().len() <= max_bytes);
+        assert!(buffer.get_ref().len() > encoded_size(30));
+
+        // Falls back to the lowest quality when nothing fits
+        let options = EncodeOptions {
+            max_bytes: Some(1),
+            ..EncodeOptions::new(ImageFormat::Jpeg)
+        };
+        let (buffer, _) = encode_image(image.clone(), options).unwrap();
+        assert_eq!(buffer.get_ref().len(), encoded_size(1));
+    }
+
+    fn create_test_frames() -> Vec<Frame> {
+        (0..3)
+            .map(|index| Frame {
+                image: DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                    16,
+                    16,
+                    Rgba([index * 100, 0, 0, 255]),
+                )),
+                delay: image::Delay::from_numer_denom_ms(100, 1),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_encodes_animated_gif() {
+        let (buffer, _) =
+            encode_frames(create_test_frames(), EncodeOptions::new(ImageFormat::Gif)).unwrap();
+        let decoder =
+            image::codecs::gif::GifDecoder::new(Cursor::new(buffer.into_inner())).unwrap();
+        let frames = image::AnimationDecoder::into_frames(decoder)
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].buffer().get_pixel(0, 0), &Rgba([100, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_encodes_animated_webp() {
+        let options = EncodeOptions {
+            lossless: true,
+            ..EncodeOptions::new(ImageFormat::WebP)
+        };
+        let (buffer, _) = encode_frames(create_test_frames(), options).unwrap();
+        let decoder =
+            image::codecs::webp::WebPDecoder::new(Cursor::new(buffer.into_inner())).unwrap();
+        assert!(decoder.has_animation());
+        let frames = image::AnimationDecoder::into_frames(decoder)
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        // The decoder blends frames onto the canvas, which can round channels down
+        assert!(frames[2].buffer().get_pixel(0, 0)[0].abs_diff(200) <= 1);
+    }
+
+    #[test]
+    fn test_encodes_apng() {
+        let (buffer, _) =
+            encode_frames(create_test_frames(), EncodeOptions::new(ImageFormat::Png)).unwrap();
+        let reader =
+            image::ImageReader::with_format(Cursor::new(buffer.into_inner()), ImageFormat::Png);
+        let frames = crate::animation::decode_frames(reader, None).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].image.get_pixel(0, 0), Rgba([100, 0, 0, 255]));
+        assert_eq!(frames[1].delay, image::Delay::from_numer_denom_ms(100, 1));
+    }
+
+    #[test]
+    fn test_encodes_quantized_apng() {
+        let options = EncodeOptions {
+            colors: Some(4),
+            ..EncodeOptions::new(ImageFormat::Png)
+        };
+        let (buffer, _) = encode_frames(create_test_frames(), options).unwrap();
+        let decoder = png::Decoder::new(Cursor::new(buffer.get_ref().as_slice()));
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.color_type, png::ColorType::Indexed);
+        assert_eq!(info.animation_control.unwrap().num_frames, 3);
+    }
+
+    #[test]
+    fn test_clamps_gif_speed() {
+        let options = EncodeOptions {
+            speed: Some(255),
+            ..EncodeOptions::new(ImageFormat::Gif)
+        };
+        let image = create_test_images().pop().unwrap();
+        assert!(encode_image(image, options).is_ok());
+        assert!(encode_frames(create_test_frames(), options).is_ok());
+    }
+
+    #[test]
+    fn test_fails_quantizing_frames_of_different_sizes() {
+        let images = [
+            DynamicImage::ImageRgba8(RgbaImage::new(8, 8)),
+            DynamicImage::ImageRgba8(RgbaImage::new(4, 4)),
+        ];
+        let delays = [image::Delay::from_numer_denom_ms(100, 1); 2];
+        let mut buffer = Cursor::new(Vec::new());
+        assert!(encode_png(&mut buffer, &images, &delays, 6, Some(4)).is_err());
+    }
+
+    #[test]
+    fn test_encodes_first_frame_for_still_formats() {
+        let (buffer, format) =
+            encode_frames(create_test_frames(), EncodeOptions::new(ImageFormat::Bmp)).unwrap();
+        assert_eq!(format, ImageFormat::Bmp);
+        let decoded = image::load_from_memory(buffer.get_ref()).unwrap();
+        assert_eq!(decoded.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_flattens_onto_white_by_default() {
+        let image =
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 16, |_, _| image::Rgba([0, 0, 0, 0])));
+        let options = EncodeOptions::new(ImageFormat::Jpeg);
+
+        let (buffer, _) = encode_image(image, options).unwrap();
+        let decoded = image::load_from_memory(buffer.get_ref()).unwrap().to_rgb8();
+        assert!(
+            decoded
+                .get_pixel(8, 8)
+                .0
+                .iter()
+                .all(|&channel| channel > 240)
+        );
+    }
+
+    #[test]
+    fn test_encodes_every_color_type_to_every_format() {
+        for format in OUTPUT_FORMATS {
+            for image in create_test_images() {
+                let color = image.color();
+                let options = EncodeOptions {
+                    speed: Some(10),
+                    ..EncodeOptions::new(format)
+                };
+
+                let result = encode_image(image, options);
+                assert!(result.is_ok(), "encoding {color:?} as {format:?} failed");
+                assert!(!result.unwrap().0.get_ref().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_converts_to_nearest_color_type() {
+        let background = Rgb([255, 255, 255]);
+        let cases = [
+            (ColorType::Rgba16, ImageFormat::Jpeg, ColorType::Rgb8),
+            (ColorType::Rgba16, ImageFormat::Qoi, ColorType::Rgba8),
+            (ColorType::La8, ImageFormat::Tiff, ColorType::Rgba8),
+            (ColorType::L8, ImageFormat::Gif, ColorType::Rgb8),
+            (ColorType::L16, ImageFormat::Jpeg, ColorType::L8),
+            (ColorType::Rgb32F, ImageFormat::Png, ColorType::Rgb16),
+            (ColorType::Rgba8, ImageFormat::Hdr, ColorType::Rgb32F),
+            (ColorType::Rgb8, ImageFormat::OpenExr, ColorType::Rgb32F),
+            (ColorType::L8, ImageFormat::Farbfeld, ColorType::Rgba16),
+        ];
+
+        for (source, format, expected) in cases {
+            let image = create_test_images()
+                .into_iter()
+                .find(|image| image.color() == source)
+                .unwrap();
+
+            let converted = convert_for_format(image, format, background).unwrap();
+            assert_eq!(converted.color(), expected, "{source:?} as {format:?}");
+        }
+    }
+
+    #[test]
+    fn test_encodes_lossy_and_lossless_webp() {
+        let image = create_test_images().pop().unwrap();
+        let encode = |quality, lossless| {
+            let options = EncodeOptions {
+                quality: Some(quality),
+                lossless,
+                ..EncodeOptions::new(ImageFormat::WebP)
+            };
+            encode_image(image.clone(), options).unwrap().0.into_inner()
+        };
+
+        let low = encode(10, false);
+        let high = encode(100, false);
+        let lossless = encode(80, true);
+        assert!(low.len() < high.len());
+        assert_ne!(high, lossless);
+        assert_eq!(
+            image::guess_format(&low).unwrap(),
+            ImageFormat::WebP,
+            "lossy output is not WebP"
+        );
+        assert!(image::load_from_memory(&low).is_ok());
+    }
+
+    #[test]
+    fn test_clamps_webp_quality() {
+        let options = EncodeOptions {
+            quality: Some(255),
+            ..EncodeOptions::new(ImageFormat::WebP)
+        };
+        let image = create_test_images().pop().unwrap();
+        assert!(encode_image(image.clone(), options).is_ok());
+        assert!(encode_frames(create_test_frames(), options).is_ok());
+
+        let options = EncodeOptions {
+            max_bytes: Some(1),
+            ..options
+        };
+        assert!(encode_image(image, options).is_ok());
+    }
 }
@@ -1,12 +1,12 @@
 pub mod web;
 
+use crate::util::format::InputFormat;
 use axum::body::Bytes;
-use image::ImageFormat;
 
 pub struct FetchResult {
     pub bytes: Bytes,
     pub filename: Option<String>,
-    pub image_format: Option<ImageFormat>,
+    pub image_format: Option<InputFormat>,
 }
 
 pub trait Fetcher {
@@ -0,0 +1,219 @@
+use crate::operation::RedactMode;
+use crate::util::color::convert_color_type;
+use image::imageops::{self, FilterType as ImageFilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// Applies a function to every pixel as normalized RGBA, keeping the original color type.
+pub fn map_pixels(image: DynamicImage, f: impl Fn([f32; 4]) -> [f32; 4]) -> DynamicImage {
+    map_pixels_with_position(image, |_, _, pixel| f(pixel))
+}
+
+pub fn map_pixels_with_position(
+    image: DynamicImage,
+    f: impl Fn(u32, u32, [f32; 4]) -> [f32; 4],
+) -> DynamicImage {
+    let color = image.color();
+    let mut buffer = image.into_rgba32f();
+    for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+        *pixel = Rgba(f(x, y, pixel.0).map(|channel| channel.clamp(0.0, 1.0)));
+    }
+
+    convert_color_type(DynamicImage::ImageRgba32F(buffer), color)
+}
+
+fn luminance([r, g, b, _]: [f32; 4]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Shifts every color channel by `value` on a 0-255 scale, regardless of the bit depth.
+pub fn brighten(image: DynamicImage, value: i32) -> DynamicImage {
+    let offset = value as f32 / 255.0;
+    map_pixels(image, |[r, g, b, a]| {
+        [r + offset, g + offset, b + offset, a]
+    })
+}
+
+/// Scales the distance of each pixel from its luminance, -100 removing all color.
+pub fn saturate(image: DynamicImage, amount: f32) -> DynamicImage {
+    let factor = 1.0 + amount / 100.0;
+    map_pixels(image, |pixel| {
+        let l = luminance(pixel);
+        let [r, g, b, a] = pixel;
+        [
+            l + (r - l) * factor,
+            l + (g - l) * factor,
+            l + (b - l) * factor,
+            a,
+        ]
+    })
+}
+
+pub fn gamma(image: DynamicImage, gamma: f32) -> DynamicImage {
+    let exponent = 1.0 / gamma;
+    map_pixels(image, |[r, g, b, a]| {
+        [r.powf(exponent), g.powf(exponent), b.powf(exponent), a]
+    })
+}
+
+pub fn grayscale(image: DynamicImage) -> DynamicImage {
+    saturate(image, -100.0)
+}
+
+pub fn sepia(image: DynamicImage) -> DynamicImage {
+    map_pixels(image, |[r, g, b, a]| {
+        [
+            0.393 * r + 0.769 * g + 0.189 * b,
+            0.349 * r + 0.686 * g + 0.168 * b,
+            0.272 * r + 0.534 * g + 0.131 * b,
+            a,
+        ]
+    })
+}
+
+/// Replaces the colors with the tint color scaled by luminance, mixed in by the tint's alpha.
+pub fn tint(image: DynamicImage, color: Rgba<u8>) -> DynamicImage {
+    let [tr, tg, tb, strength] = color.0.map(|channel| channel as f32 / 255.0);
+    map_pixels(image, |pixel| {
+        let l = luminance(pixel);
+        let [r, g, b, a] = pixel;
+        [
+            r + (l * tr - r) * strength,
+            g + (l * tg - g) * strength,
+            b + (l * tb - b) * strength,
+            a,
+        ]
+    })
+}
+
+/// Averages blocks of `size` by `size` pixels, aligned to the top-left corner.
+pub fn pixelate(image: DynamicImage, size: u32) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    if size <= 1 || width == 0 || height == 0 {
+        return image;
+    }
+
+    let small = image.resize_exact(
+        width.div_ceil(size),
+        height.div_ceil(size),
+        ImageFilterType::Triangle,
+    );
+    small
+        .resize_exact(
+            small.width() * size,
+            small.height() * size,
+            ImageFilterType::Nearest,
+        )
+        .crop_imm(0, 0, width, height)
+}
+
+/// Obscures a region of the image. The region is clamped to the image bounds.
+pub fn redact(
+    image: DynamicImage,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    mode: RedactMode,
+) -> DynamicImage {
+    let x = x.min(image.width());
+    let y = y.min(image.height());
+    let width = width.min(image.width() - x);
+    let height = height.min(image.height() - y);
+    if width == 0 || height == 0 {
+        return image;
+    }
+
+    let region = image.crop_imm(x, y, width, height);
+    let strength = width.max(height) / 8;
+    let region = match mode {
+        RedactMode::Pixelate => pixelate(region, strength.max(2)),
+        // Capped like the `blur` operation, since the kernel grows with the sigma
+        RedactMode::Blur => region.blur(strength.clamp(1, 100) as f32),
+        RedactMode::Fill => convert_color_type(
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]))),
+            region.color(),
+        ),
+    };
+    let mut image = image;
+    imageops::replace(&mut image, &region, x as i64, y as i64);
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ColorType, Rgb, RgbImage};
+
+    fn create_test_image() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(4, 4, |_, _| Rgb([200, 100, 50])))
+    }
+
+    #[test]
+    fn test_keeps_color_type() {
+        let image = DynamicImage::ImageLuma16(create_test_image().to_luma16());
+        let result = brighten(image, 10);
+        assert_eq!(result.color(), ColorType::L16);
+    }
+
+    #[test]
+    fn test_brightens() {
+        let result = brighten(create_test_image(), 60);
+        assert_eq!(result.to_rgb8().get_pixel(0, 0), &Rgb([255, 160, 110]));
+    }
+
+    #[test]
+    fn test_desaturates() {
+        let result = saturate(create_test_image(), -100.0);
+        let [r, g, b] = result.to_rgb8().get_pixel(0, 0).0;
+        assert_eq!(r, g);
+        assert_eq!(g, b);
+    }
+
+    #[test]
+    fn test_applies_gamma() {
+        let result = gamma(create_test_image(), 2.0);
+        let [r, g, b] = result.to_rgb8().get_pixel(0, 0).0;
+        assert!(r > 200 && g > 100 && b > 50);
+    }
+
+    #[test]
+    fn test_applies_sepia() {
+        let result = sepia(create_test_image());
+        let [r, g, b] = result.to_rgb8().get_pixel(0, 0).0;
+        assert!(r > g && g > b);
+    }
+
+    #[test]
+    fn test_tints() {
+        let result = tint(create_test_image(), Rgba([255, 0, 0, 255]));
+        let [r, g, b] = result.to_rgb8().get_pixel(0, 0).0;
+        assert!(r > 0);
+        assert_eq!((g, b), (0, 0));
+
+        let result = tint(create_test_image(), Rgba([255, 0, 0, 0]));
+        assert_eq!(result.to_rgb8().get_pixel(0, 0), &Rgb([200, 100, 50]));
+    }
+
+    #[test]
+    fn test_pixelates() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(10, 10, |x, _| {
+            Rgb([if x % 2 == 0 { 0 } else { 255 }, 0, 0])
+        }));
+
+        let result = pixelate(image, 4).to_rgb8();
+        assert_eq!((result.width(), result.height()), (10, 10));
+        assert_eq!(result.get_pixel(0, 0), result.get_pixel(3, 3));
+        let [r, _, _] = result.get_pixel(0, 0).0;
+        assert!(r > 64 && r < 192);
+    }
+
+    #[test]
+    fn test_redacts_region() {
+        let result = redact(create_test_image(), 1, 1, 10, 2, RedactMode::Fill).to_rgb8();
+        assert_eq!(result.get_pixel(0, 0), &Rgb([200, 100, 50]));
+        assert_eq!(result.get_pixel(1, 1), &Rgb([0, 0, 0]));
+        assert_eq!(result.get_pixel(3, 2), &Rgb([0, 0, 0]));
+        assert_eq!(result.get_pixel(3, 3), &Rgb([200, 100, 50]));
+    }
+}
@@ -1,18 +1,30 @@
+mod animation;
+mod composite;
+mod crop;
+mod decode;
 mod encode;
 mod fetcher;
+mod filter;
+mod mask;
 mod operation;
+mod page;
 mod params;
+mod quantize;
 mod routes;
 mod signature;
+mod svg;
+mod text;
 mod util;
 
 use crate::fetcher::Fetcher;
 use crate::fetcher::web::WebFetcher;
 use crate::routes::health::health;
 use crate::routes::process::process;
+use crate::text::Fonts;
 use axum::Router;
 use axum::routing::get;
 use dotenvy::dotenv;
+use image::DynamicImage;
 use reqwest::Url;
 use std::env;
 use std::sync::Arc;
@@ -27,6 +39,8 @@ static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 #[derive(Clone)]
 struct AppState {
     key: Option<Arc<str>>,
+    watermark: Option<Arc<DynamicImage>>,
+    fonts: Arc<Fonts>,
     web_fetcher: Arc<WebFetcher>,
 }
 
@@ -68,12 +82,26 @@ async fn main() {
         );
     }
 
+    let watermark = env::var("WATERMARK_PATH")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(|path| Arc::new(image::open(path).expect("failed to load watermark")));
+
+    let fonts = env::var("FONTS_PATH")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(|path| Fonts::load(path).expect("failed to load fonts"))
+        .unwrap_or_default();
+    info!("Loaded {} fonts", fonts.names().count());
+
     let app = Router::new()
         .route("/healthz", get(health))
         .route("/{signature}/{*rest}", get(process))
         .layer(TraceLayer::new_for_http())
         .with_state(AppState {
             key,
+            watermark,
+            fonts: Arc::new(fonts),
             web_fetcher: Arc::new(WebFetcher::new()),
         });
 
@@ -0,0 +1,86 @@
+use crate::filter::map_pixels_with_position;
+use crate::util::color::{convert_color_type, with_alpha};
+use image::DynamicImage;
+
+/// Multiplies the alpha channel by the coverage of each pixel, adding an alpha channel if the
+/// image has none.
+fn mask(image: DynamicImage, coverage: impl Fn(f32, f32) -> f32) -> DynamicImage {
+    let color = with_alpha(image.color());
+    let image = convert_color_type(image, color);
+    map_pixels_with_position(image, |x, y, [r, g, b, a]| {
+        [r, g, b, a * coverage(x as f32 + 0.5, y as f32 + 0.5)]
+    })
+}
+
+/// Coverage of a pixel centered at the given distance from the edge of a circle, antialiased over
+/// one pixel.
+fn circle_coverage(distance: f32, radius: f32) -> f32 {
+    (radius - distance + 0.5).clamp(0.0, 1.0)
+}
+
+pub fn round_corners(image: DynamicImage, radius: u32) -> DynamicImage {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let radius = (radius as f32).min(width / 2.0).min(height / 2.0);
+    if radius <= 0.0 {
+        return image;
+    }
+
+    mask(image, |x, y| {
+        let corner_x = x.clamp(radius, width - radius);
+        let corner_y = y.clamp(radius, height - radius);
+        let distance = ((x - corner_x).powi(2) + (y - corner_y).powi(2)).sqrt();
+        circle_coverage(distance, radius)
+    })
+}
+
+/// Crops the image to a centered square and masks it with the largest circle that fits.
+pub fn circle(image: DynamicImage) -> DynamicImage {
+    let size = image.width().min(image.height());
+    let image = image.crop_imm(
+        (image.width() - size) / 2,
+        (image.height() - size) / 2,
+        size,
+        size,
+    );
+
+    let radius = size as f32 / 2.0;
+    mask(image, |x, y| {
+        let distance = ((x - radius).powi(2) + (y - radius).powi(2)).sqrt();
+        circle_coverage(distance, radius)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ColorType, Rgb, RgbImage};
+
+    fn create_test_image() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(40, 20, |_, _| Rgb([255, 0, 0])))
+    }
+
+    #[test]
+    fn test_rounds_corners() {
+        let result = round_corners(create_test_image(), 8);
+        assert_eq!(result.color(), ColorType::Rgba8);
+
+        let result = result.to_rgba8();
+        assert_eq!(result.get_pixel(0, 0).0[3], 0);
+        assert_eq!(result.get_pixel(39, 19).0[3], 0);
+        assert_eq!(result.get_pixel(8, 0).0[3], 255);
+        assert_eq!(result.get_pixel(20, 10).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_masks_circle() {
+        let result = circle(create_test_image());
+        assert_eq!((result.width(), result.height()), (20, 20));
+
+        let result = result.to_rgba8();
+        assert_eq!(result.get_pixel(0, 0).0[3], 0);
+        assert!(result.get_pixel(10, 0).0[3] > 200);
+        assert_eq!(result.get_pixel(10, 1).0[3], 255);
+        assert_eq!(result.get_pixel(10, 10).0[3], 255);
+        assert_eq!(result.get_pixel(19, 19).0[3], 0);
+    }
+}
@@ -1,6 +1,14 @@
-use crate::encode::EncodeOptions;
+use crate::animation::{self, Frame};
+use crate::composite;
+use crate::crop::{self, CropWindows};
+use crate::encode::{EncodeOptions, Subsampling, supports_alpha};
+use crate::filter;
+use crate::mask;
+use crate::text::{self, Fonts};
 use image::imageops::FilterType as ImageFilterType;
-use image::{DynamicImage, ImageFormat};
+use image::{DynamicImage, ImageFormat, Rgb, Rgba};
+use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Clone, Copy)]
 pub enum Rotation {
@@ -9,30 +17,223 @@ pub enum Rotation {
     Rotate270,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Length {
+    Pixels(u32),
+    Percent(f32),
+}
+
+impl Length {
+    pub fn resolve(self, total: u32) -> u32 {
+        match self {
+            Length::Pixels(pixels) => pixels,
+            Length::Percent(percent) => (total as f32 * percent / 100.0).round() as u32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gravity {
+    North,
+    South,
+    East,
+    West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
+    Center,
+}
+
+impl Gravity {
+    /// Resolves the top-left corner of an item placed on a canvas. Offsets move the item away
+    /// from the edge it is anchored to, or right and down when it is centered.
+    pub fn position(self, canvas: (u32, u32), item: (u32, u32), offset: (i32, i32)) -> (i64, i64) {
+        let free_x = canvas.0 as i64 - item.0 as i64;
+        let free_y = canvas.1 as i64 - item.1 as i64;
+        let (offset_x, offset_y) = (offset.0 as i64, offset.1 as i64);
+
+        let x = match self {
+            Gravity::West | Gravity::NorthWest | Gravity::SouthWest => offset_x,
+            Gravity::East | Gravity::NorthEast | Gravity::SouthEast => free_x - offset_x,
+            Gravity::North | Gravity::South | Gravity::Center => free_x / 2 + offset_x,
+        };
+        let y = match self {
+            Gravity::North | Gravity::NorthEast | Gravity::NorthWest => offset_y,
+            Gravity::South | Gravity::SouthEast | Gravity::SouthWest => free_y - offset_y,
+            Gravity::East | Gravity::West | Gravity::Center => free_y / 2 + offset_y,
+        };
+
+        (x, y)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CropGravity {
+    Gravity(Gravity),
+    Smart,
+    /// Point to center the crop on, as fractions of the image width and height
+    FocalPoint(f32, f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RedactMode {
+    Pixelate,
+    Blur,
+    Fill,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatermarkPosition {
+    Gravity(Gravity),
+    Repeat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watermark {
+    pub opacity: f32,
+    pub position: WatermarkPosition,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub scale: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overlay {
+    pub url: String,
+    pub gravity: Gravity,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub opacity: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Text {
+    pub text: String,
+    pub size: f32,
+    pub color: Rgba<u8>,
+    pub gravity: Gravity,
+    pub font: Option<String>,
+}
+
+/// Images and other resources loaded outside of the request that operations may use.
+#[derive(Default)]
+pub struct Assets {
+    pub watermark: Option<Arc<DynamicImage>>,
+    pub overlays: HashMap<String, DynamicImage>,
+    pub fonts: Arc<Fonts>,
+}
+
 pub enum Operation {
     Format(ImageFormat),
     Speed(u8),
     Quality(u8),
-    Resize(u32, u32),
+    Lossless(bool),
+    Progressive(bool),
+    Subsampling(Subsampling),
+    Compression(u8),
+    Colors(u16),
+    MaxBytes(usize),
+    Frame(usize),
+    Page(usize),
+    Resize(Length, Length),
     Rotate(Rotation),
+    Background(Rgb<u8>),
+    Blur(f32),
+    Sharpen(f32, i32),
+    Brightness(i32),
+    Contrast(f32),
+    Saturation(f32),
+    Hue(i32),
+    Gamma(f32),
+    Grayscale,
+    Sepia,
+    Invert,
+    Tint(Rgba<u8>),
+    Watermark(Watermark),
+    Overlay(Overlay),
+    Text(Text),
+    Radius(Length),
+    Circle,
+    Trim(u8, Option<Rgba<u8>>),
+    Gravity(CropGravity),
+    Crop(Length, Length),
+    Fill(Length, Length),
+    AspectRatio(u32, u32),
+    Pixelate(u32),
+    Redact(u32, u32, u32, u32, RedactMode),
+    Border(u32, Rgba<u8>, bool),
+}
+
+/// Format requested by the operations, or the input format if none was, before falling back to
+/// a format with transparency for masks.
+pub fn output_format(input_format: ImageFormat, operations: &[Operation]) -> ImageFormat {
+    operations
+        .iter()
+        .rev()
+        .find_map(|operation| match *operation {
+            Operation::Format(format) => Some(format),
+            _ => None,
+        })
+        .unwrap_or(input_format)
+}
+
+/// Applies the operations to every frame of an animation. Trimming and smart crops are decided on
+/// the first frame and reused for the others, and frames that still end up a different size than
+/// the first one are cropped or padded to match it.
+pub fn apply_operations_to_frames(
+    frames: Vec<Frame>,
+    input_format: ImageFormat,
+    operations: &[Operation],
+    assets: &Assets,
+) -> anyhow::Result<(Vec<Frame>, EncodeOptions)> {
+    let mut output_options = EncodeOptions::new(input_format);
+    let mut size = None;
+    let mut windows = CropWindows::default();
+    let frames = frames
+        .into_iter()
+        .map(|frame| {
+            windows.rewind();
+            let (image, options) =
+                apply_operations(frame.image, input_format, operations, assets, &mut windows)?;
+            output_options = options;
+            let (width, height) = *size.get_or_insert((image.width(), image.height()));
+            Ok(Frame {
+                image: animation::fit_canvas(image, width, height),
+                delay: frame.delay,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok((frames, output_options))
 }
 
 pub fn apply_operations(
     image: DynamicImage,
     input_format: ImageFormat,
     operations: &[Operation],
-) -> (DynamicImage, EncodeOptions) {
+    assets: &Assets,
+    windows: &mut CropWindows,
+) -> anyhow::Result<(DynamicImage, EncodeOptions)> {
     let mut image = image;
-    let mut output_options = EncodeOptions {
-        format: input_format,
-        speed: None,
-        quality: None,
-    };
+    let mut format_set = false;
+    let mut transparent = false;
+    // Gravity applies to the crops that follow it
+    let mut crop_gravity = CropGravity::Gravity(Gravity::Center);
+    let mut output_options = EncodeOptions::new(input_format);
+
+    // Trimming removes borders of the source image, so it runs before other operations
+    let is_trim = |operation: &&Operation| matches!(operation, Operation::Trim(..));
+    let operations = operations
+        .iter()
+        .filter(is_trim)
+        .chain(operations.iter().filter(|operation| !is_trim(operation)));
 
     for operation in operations {
         match *operation {
             Operation::Format(format) => {
                 output_options.format = format;
+                format_set = true;
             }
 
             Operation::Speed(speed) => {
@@ -43,8 +244,36 @@ pub fn apply_operations(
                 output_options.quality = Some(quality);
             }
 
+            Operation::Lossless(lossless) => {
+                output_options.lossless = lossless;
+            }
+
+            Operation::Progressive(progressive) => {
+                output_options.progressive = progressive;
+            }
+
+            Operation::Subsampling(subsampling) => {
+                output_options.subsampling = Some(subsampling);
+            }
+
+            Operation::Compression(compression) => {
+                output_options.compression = Some(compression);
+            }
+
+            Operation::Colors(colors) => {
+                output_options.colors = Some(colors);
+            }
+
+            Operation::MaxBytes(max_bytes) => {
+                output_options.max_bytes = Some(max_bytes);
+            }
+
             Operation::Resize(width, height) => {
-                image = image.resize(width, height, ImageFilterType::Lanczos3);
+                image = image.resize(
+                    width.resolve(image.width()),
+                    height.resolve(image.height()),
+                    ImageFilterType::Lanczos3,
+                );
             }
 
             Operation::Rotate(rotation) => {
@@ -54,10 +283,150 @@ pub fn apply_operations(
                     Rotation::Rotate270 => image.rotate270(),
                 }
             }
+
+            Operation::Background(color) => {
+                output_options.background = Some(color);
+            }
+
+            Operation::Blur(sigma) => {
+                image = image.blur(sigma);
+            }
+
+            Operation::Sharpen(sigma, threshold) => {
+                image = image.unsharpen(sigma, threshold);
+            }
+
+            Operation::Brightness(value) => {
+                image = filter::brighten(image, value);
+            }
+
+            Operation::Contrast(contrast) => {
+                image = image.adjust_contrast(contrast);
+            }
+
+            Operation::Saturation(amount) => {
+                image = filter::saturate(image, amount);
+            }
+
+            Operation::Hue(degrees) => {
+                image = image.huerotate(degrees);
+            }
+
+            Operation::Gamma(gamma) => {
+                image = filter::gamma(image, gamma);
+            }
+
+            Operation::Grayscale => {
+                image = filter::grayscale(image);
+            }
+
+            Operation::Sepia => {
+                image = filter::sepia(image);
+            }
+
+            Operation::Invert => {
+                image.invert();
+            }
+
+            Operation::Tint(color) => {
+                image = filter::tint(image, color);
+            }
+
+            Operation::Watermark(options) => {
+                if let Some(watermark) = &assets.watermark {
+                    image = composite::watermark(image, watermark, options)?;
+                }
+            }
+
+            Operation::Overlay(ref overlay) => {
+                if let Some(layer) = assets.overlays.get(&overlay.url) {
+                    let position = overlay.gravity.position(
+                        (image.width(), image.height()),
+                        (layer.width(), layer.height()),
+                        (overlay.x_offset, overlay.y_offset),
+                    );
+                    image = composite::composite(image, layer, &[position], overlay.opacity);
+                }
+            }
+
+            Operation::Text(ref options) => {
+                if let Some(font) = assets.fonts.get(options.font.as_deref()) {
+                    let layer = DynamicImage::ImageRgba8(text::render_text(
+                        font,
+                        &options.text,
+                        options.size,
+                        options.color,
+                    )?);
+                    let position = options.gravity.position(
+                        (image.width(), image.height()),
+                        (layer.width(), layer.height()),
+                        (0, 0),
+                    );
+                    image = composite::composite(image, &layer, &[position], 1.0);
+                }
+            }
+
+            Operation::Radius(radius) => {
+                let radius = radius.resolve(image.width().min(image.height()));
+                image = mask::round_corners(image, radius);
+                transparent = true;
+            }
+
+            Operation::Circle => {
+                image = mask::circle(image);
+                transparent = true;
+            }
+
+            Operation::Trim(threshold, color) => {
+                image = crop::trim(image, threshold, color, windows);
+            }
+
+            Operation::Gravity(gravity) => {
+                crop_gravity = gravity;
+            }
+
+            Operation::Frame(_) | Operation::Page(_) => {}
+
+            Operation::Crop(width, height) => {
+                let (width, height) =
+                    (width.resolve(image.width()), height.resolve(image.height()));
+                image = crop::crop(image, width, height, crop_gravity, windows);
+            }
+
+            Operation::Fill(width, height) => {
+                let (width, height) =
+                    (width.resolve(image.width()), height.resolve(image.height()));
+                image = crop::fill(image, width, height, crop_gravity, windows);
+            }
+
+            Operation::AspectRatio(width, height) => {
+                image = crop::aspect_ratio(image, width, height, crop_gravity, windows);
+            }
+
+            Operation::Pixelate(size) => {
+                image = filter::pixelate(image, size);
+            }
+
+            Operation::Redact(x, y, width, height, mode) => {
+                image = filter::redact(image, x, y, width, height, mode);
+            }
+
+            Operation::Border(width, color, inside) => {
+                image = composite::border(image, width, color, inside);
+            }
         }
     }
 
-    (image, output_options)
+    // Keep masked corners transparent unless the output format or background was chosen
+    if transparent
+        && !format_set
+        && output_options.background.is_none()
+        && !supports_alpha(output_options.format)
+    {
+        output_options.format = ImageFormat::Png;
+    }
+
+    Ok((image, output_options))
 }
 
 #[cfg(test)]
@@ -75,19 +444,486 @@ mod tests {
     fn test_applies_operations() {
         let image = create_test_image();
         let operations = vec![
-            Operation::Resize(48, 32),
+            Operation::Resize(Length::Pixels(48), Length::Pixels(32)),
             Operation::Rotate(Rotation::Rotate180),
             Operation::Quality(90),
             Operation::Speed(2),
             Operation::Format(ImageFormat::Jpeg),
+            Operation::Background(Rgb([0, 0, 0])),
+            Operation::Lossless(true),
+            Operation::Progressive(true),
+            Operation::Subsampling(Subsampling::Yuv420),
+            Operation::Compression(9),
+            Operation::Colors(64),
+            Operation::MaxBytes(20000),
         ];
 
-        let (output_image, options) = apply_operations(image, ImageFormat::Png, &operations);
+        let (output_image, options) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
 
         assert_eq!(output_image.width(), 32);
         assert_eq!(output_image.height(), 32);
         assert_eq!(options.format, ImageFormat::Jpeg);
         assert_eq!(options.quality, Some(90));
         assert_eq!(options.speed, Some(2));
+        assert_eq!(options.background, Some(Rgb([0, 0, 0])));
+        assert!(options.lossless);
+        assert!(options.progressive);
+        assert_eq!(options.subsampling, Some(Subsampling::Yuv420));
+        assert_eq!(options.compression, Some(9));
+        assert_eq!(options.colors, Some(64));
+        assert_eq!(options.max_bytes, Some(20000));
+    }
+
+    #[test]
+    fn test_resolves_output_format() {
+        assert_eq!(output_format(ImageFormat::Gif, &[]), ImageFormat::Gif);
+        let operations = [
+            Operation::Format(ImageFormat::WebP),
+            Operation::Circle,
+            Operation::Format(ImageFormat::Jpeg),
+        ];
+        assert_eq!(
+            output_format(ImageFormat::Gif, &operations),
+            ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn test_applies_operations_to_frames() {
+        let frames = (0..2)
+            .map(|index| Frame {
+                image: create_test_image(),
+                delay: image::Delay::from_numer_denom_ms(index * 50, 1),
+            })
+            .collect();
+        let operations = vec![
+            Operation::Resize(Length::Pixels(32), Length::Pixels(32)),
+            Operation::Format(ImageFormat::Gif),
+        ];
+
+        let (frames, options) =
+            apply_operations_to_frames(frames, ImageFormat::Png, &operations, &Assets::default())
+                .unwrap();
+
+        assert_eq!(options.format, ImageFormat::Gif);
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|frame| frame.image.width() == 32));
+        assert_eq!(frames[1].delay, image::Delay::from_numer_denom_ms(50, 1));
+    }
+
+    #[test]
+    fn test_trims_every_frame_the_same_way() {
+        // A dot moving across a white background, which would be trimmed differently per frame
+        let frames = [(2, 2), (10, 6)]
+            .into_iter()
+            .map(|(dot_x, dot_y)| {
+                Frame::still(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+                    16,
+                    16,
+                    |x, y| {
+                        if (dot_x..dot_x + 4).contains(&x) && (dot_y..dot_y + 4).contains(&y) {
+                            image::Rgba([0, 0, 0, 255])
+                        } else {
+                            image::Rgba([255, 255, 255, 255])
+                        }
+                    },
+                )))
+            })
+            .collect();
+        let operations = vec![Operation::Trim(0, None)];
+
+        let (frames, _) =
+            apply_operations_to_frames(frames, ImageFormat::Png, &operations, &Assets::default())
+                .unwrap();
+
+        assert_eq!(frames[0].image.to_rgba8().get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(frames[1].image.to_rgba8().get_pixel(0, 0).0, [255; 4]);
+        assert!(
+            frames
+                .iter()
+                .all(|frame| (frame.image.width(), frame.image.height()) == (4, 4))
+        );
+    }
+
+    #[test]
+    fn test_blurs_and_sharpens() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 16, |x, _| {
+            if x < 8 {
+                image::Rgba([0, 0, 0, 255])
+            } else {
+                image::Rgba([255, 255, 255, 255])
+            }
+        }));
+
+        let (blurred, _) = apply_operations(
+            image.clone(),
+            ImageFormat::Png,
+            &[Operation::Blur(2.0)],
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+        let edge = blurred.to_rgba8().get_pixel(7, 8).0[0];
+        assert!(edge > 0 && edge < 255);
+
+        let (sharpened, _) = apply_operations(
+            blurred,
+            ImageFormat::Png,
+            &[Operation::Sharpen(2.0, 0)],
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+        assert!(sharpened.to_rgba8().get_pixel(7, 8).0[0] < edge);
+        assert_eq!(sharpened.width(), image.width());
+    }
+
+    #[test]
+    fn test_adjusts_colors() {
+        let image = create_test_image();
+        let operations = vec![
+            Operation::Brightness(-55),
+            Operation::Contrast(10.0),
+            Operation::Saturation(20.0),
+            Operation::Hue(90),
+            Operation::Gamma(1.2),
+        ];
+
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        let [r, g, b, a] = output_image.to_rgba8().get_pixel(0, 0).0;
+        assert!(r < 255 && r.abs_diff(g) <= 1 && g.abs_diff(b) <= 1);
+        assert_eq!(a, 255);
+    }
+
+    #[test]
+    fn test_applies_filters() {
+        let image = create_test_image();
+        let operations = vec![Operation::Sepia, Operation::Grayscale, Operation::Invert];
+
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        let [r, g, b, a] = output_image.to_rgba8().get_pixel(0, 0).0;
+        assert!(r < 64 && r.abs_diff(g) <= 1 && g.abs_diff(b) <= 1);
+        assert_eq!(a, 255);
+    }
+
+    #[test]
+    fn test_applies_watermark() {
+        let image = create_test_image();
+        let assets = Assets {
+            watermark: Some(Arc::new(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+                4,
+                4,
+                |_, _| image::Rgba([0, 0, 0, 255]),
+            )))),
+            ..Default::default()
+        };
+        let operations = vec![Operation::Watermark(Watermark {
+            opacity: 1.0,
+            position: WatermarkPosition::Gravity(Gravity::Center),
+            x_offset: 0,
+            y_offset: 0,
+            scale: 0.0,
+        })];
+
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &assets,
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        let output_image = output_image.to_rgba8();
+        assert_eq!(output_image.get_pixel(32, 32).0, [0, 0, 0, 255]);
+        assert_eq!(output_image.get_pixel(0, 0).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_resolves_gravity_position() {
+        let canvas = (100, 50);
+        let item = (10, 10);
+        let offset = (5, 5);
+
+        assert_eq!(Gravity::NorthWest.position(canvas, item, offset), (5, 5));
+        assert_eq!(Gravity::SouthEast.position(canvas, item, offset), (85, 35));
+        assert_eq!(Gravity::North.position(canvas, item, offset), (50, 5));
+        assert_eq!(Gravity::East.position(canvas, item, offset), (85, 25));
+        assert_eq!(Gravity::Center.position(canvas, item, (0, 0)), (45, 20));
+    }
+
+    #[test]
+    fn test_applies_overlay() {
+        let image = create_test_image();
+        let assets = Assets {
+            overlays: HashMap::from([(
+                "https://example.com/badge.png".to_string(),
+                DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 8, |_, _| {
+                    image::Rgba([255, 0, 0, 255])
+                })),
+            )]),
+            ..Default::default()
+        };
+        let operations = vec![Operation::Overlay(Overlay {
+            url: "https://example.com/badge.png".to_string(),
+            gravity: Gravity::SouthEast,
+            x_offset: 0,
+            y_offset: 0,
+            opacity: 1.0,
+        })];
+
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &assets,
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        let output_image = output_image.to_rgba8();
+        assert_eq!(output_image.get_pixel(63, 63).0, [255, 0, 0, 255]);
+        assert_eq!(output_image.get_pixel(55, 55).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_switches_to_alpha_capable_format_when_masking() {
+        let (_, options) = apply_operations(
+            create_test_image(),
+            ImageFormat::Jpeg,
+            &[Operation::Circle],
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+        assert_eq!(options.format, ImageFormat::Png);
+
+        let (_, options) = apply_operations(
+            create_test_image(),
+            ImageFormat::Jpeg,
+            &[
+                Operation::Radius(Length::Percent(10.0)),
+                Operation::Format(ImageFormat::Jpeg),
+            ],
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+        assert_eq!(options.format, ImageFormat::Jpeg);
+
+        let (_, options) = apply_operations(
+            create_test_image(),
+            ImageFormat::Jpeg,
+            &[
+                Operation::Radius(Length::Pixels(8)),
+                Operation::Background(Rgb([0, 0, 0])),
+            ],
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+        assert_eq!(options.format, ImageFormat::Jpeg);
+
+        let (_, options) = apply_operations(
+            create_test_image(),
+            ImageFormat::WebP,
+            &[Operation::Circle],
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+        assert_eq!(options.format, ImageFormat::WebP);
+    }
+
+    #[test]
+    fn test_resolves_length() {
+        assert_eq!(Length::Pixels(20).resolve(200), 20);
+        assert_eq!(Length::Percent(12.5).resolve(200), 25);
+    }
+
+    #[test]
+    fn test_trims_before_other_operations() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, _| {
+            if x < 32 {
+                image::Rgba([255, 255, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
+        }));
+        let operations = vec![
+            Operation::Rotate(Rotation::Rotate90),
+            Operation::Trim(0, None),
+        ];
+
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        assert_eq!(output_image.width(), 64);
+        assert_eq!(output_image.height(), 32);
+    }
+
+    #[test]
+    fn test_crops_and_fills_with_gravity() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 32, |x, _| {
+            image::Rgba([x as u8, 0, 0, 255])
+        }));
+        let operations = vec![
+            Operation::Gravity(CropGravity::Gravity(Gravity::West)),
+            Operation::Crop(Length::Pixels(48), Length::Pixels(32)),
+            Operation::Gravity(CropGravity::Gravity(Gravity::East)),
+            Operation::Fill(Length::Pixels(16), Length::Pixels(16)),
+        ];
+
+        let (output_image, _) = apply_operations(
+            image.clone(),
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        // West keeps columns 0-47, then east keeps columns 16-47 of those after resizing
+        assert_eq!(output_image.width(), 16);
+        assert_eq!(output_image.height(), 16);
+        let left = output_image.to_rgba8().get_pixel(0, 0).0[0];
+        assert!((15..=18).contains(&left));
+
+        // Gravity given after a crop does not affect it
+        let operations = vec![
+            Operation::Crop(Length::Pixels(16), Length::Pixels(32)),
+            Operation::Gravity(CropGravity::Gravity(Gravity::West)),
+        ];
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+        assert_eq!(output_image.to_rgba8().get_pixel(0, 0).0[0], 24);
+    }
+
+    #[test]
+    fn test_fills_around_focal_point() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 32, |x, _| {
+            image::Rgba([x as u8, 0, 0, 255])
+        }));
+        let operations = vec![
+            Operation::Gravity(CropGravity::FocalPoint(0.75, 0.5)),
+            Operation::Fill(Length::Pixels(16), Length::Pixels(16)),
+        ];
+
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        let left = output_image.to_rgba8().get_pixel(0, 0).0[0];
+        assert!((30..=34).contains(&left));
+    }
+
+    #[test]
+    fn test_pixelates_and_redacts() {
+        let image = create_test_image();
+        let operations = vec![
+            Operation::Pixelate(8),
+            Operation::Redact(0, 0, 16, 16, RedactMode::Fill),
+            Operation::Redact(16, 16, 16, 16, RedactMode::Blur),
+            Operation::Redact(32, 32, 16, 16, RedactMode::Pixelate),
+        ];
+
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        let output_image = output_image.to_rgba8();
+        assert_eq!(output_image.get_pixel(8, 8).0, [0, 0, 0, 255]);
+        assert_eq!(output_image.get_pixel(60, 60).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_draws_border() {
+        let image = create_test_image();
+        let operations = vec![
+            Operation::Border(2, Rgba([0, 0, 0, 255]), true),
+            Operation::Border(4, Rgba([255, 0, 0, 255]), false),
+        ];
+
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        assert_eq!((output_image.width(), output_image.height()), (72, 72));
+        let output_image = output_image.to_rgba8();
+        assert_eq!(output_image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(output_image.get_pixel(5, 5).0, [0, 0, 0, 255]);
+        assert_eq!(output_image.get_pixel(36, 36).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_resizes_by_percentage_and_aspect_ratio() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(200, 100));
+        let operations = vec![
+            Operation::Resize(Length::Percent(50.0), Length::Percent(50.0)),
+            Operation::AspectRatio(1, 1),
+            Operation::Crop(Length::Percent(50.0), Length::Pixels(10)),
+        ];
+
+        let (output_image, _) = apply_operations(
+            image,
+            ImageFormat::Png,
+            &operations,
+            &Assets::default(),
+            &mut CropWindows::default(),
+        )
+        .unwrap();
+
+        assert_eq!(output_image.width(), 25);
+        assert_eq!(output_image.height(), 10);
     }
 }
@@ -0,0 +1,248 @@
+use anyhow::{anyhow, bail};
+use image::{DynamicImage, ImageFormat, ImageReader};
+use std::collections::HashSet;
+use std::io::{BufRead, Cursor, Read, Seek};
+
+/// Size of the ICO file header and of each directory entry.
+const ICO_HEADER_SIZE: usize = 6;
+const ICO_ENTRY_SIZE: usize = 16;
+
+/// Decodes the page at `index` of multi-page TIFF images, or the entry at `index` of ICO files in
+/// the order they are stored. Other images only have a single page.
+pub fn decode_page<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    index: usize,
+) -> anyhow::Result<DynamicImage> {
+    match reader.format() {
+        Some(ImageFormat::Tiff) if index > 0 => decode_tiff_page(reader.into_inner(), index),
+        Some(ImageFormat::Ico) => decode_ico_entry(reader.into_inner(), index),
+        _ => {
+            ensure_single_page(Some(index))?;
+            Ok(reader.decode()?)
+        }
+    }
+}
+
+/// Fails if a page after the first was requested from an image with a single page.
+pub fn ensure_single_page(index: Option<usize>) -> anyhow::Result<()> {
+    if index.is_some_and(|index| index > 0) {
+        bail!("Page not found");
+    }
+
+    Ok(())
+}
+
+/// Decodes a TIFF page by pointing the header at its directory, since the decoder always reads
+/// the first one. Going through `image` handles the same color types as the first page.
+fn decode_tiff_page<R: Read>(mut reader: R, index: usize) -> anyhow::Result<DynamicImage> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let read = |offset: u64, size: usize| -> anyhow::Result<u64> {
+        let bytes = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| data.get(offset..offset.checked_add(size)?))
+            .ok_or(anyhow!("Invalid TIFF file"))?;
+        let mut value = [0; 8];
+        if data.starts_with(b"II") {
+            value[..size].copy_from_slice(bytes);
+            Ok(u64::from_le_bytes(value))
+        } else {
+            value[8 - size..].copy_from_slice(bytes);
+            Ok(u64::from_be_bytes(value))
+        }
+    };
+
+    // BigTIFF uses 8-byte offsets and counts, and 20-byte directory entries
+    let (pointer, offset_size, count_size, entry_size) = match read(2, 2)? {
+        42 => (4, 4, 2, 12),
+        43 => (8, 8, 8, 20),
+        _ => bail!("Invalid TIFF file"),
+    };
+
+    let mut offset = read(pointer, offset_size)?;
+    let mut visited = HashSet::new();
+    for _ in 0..index {
+        // Directories linking back to an earlier one would never end
+        if offset == 0 || !visited.insert(offset) {
+            bail!("Page not found");
+        }
+        let count = read(offset, count_size)?;
+        let next = count
+            .checked_mul(entry_size)
+            .and_then(|size| size.checked_add(offset + count_size as u64))
+            .ok_or(anyhow!("Invalid TIFF file"))?;
+        offset = read(next, offset_size)?;
+    }
+    if offset == 0 {
+        bail!("Page not found");
+    }
+
+    let pointer = pointer as usize;
+    if data.starts_with(b"II") {
+        data[pointer..pointer + offset_size].copy_from_slice(&offset.to_le_bytes()[..offset_size]);
+    } else {
+        data[pointer..pointer + offset_size]
+            .copy_from_slice(&offset.to_be_bytes()[8 - offset_size..]);
+    }
+
+    Ok(ImageReader::with_format(Cursor::new(data), ImageFormat::Tiff).decode()?)
+}
+
+/// Decodes a single ICO entry by rewriting the file with only that entry in its directory, since
+/// the decoder always picks the largest one.
+fn decode_ico_entry<R: Read>(mut reader: R, index: usize) -> anyhow::Result<DynamicImage> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let count = data
+        .get(4..6)
+        .map(|count| u16::from_le_bytes([count[0], count[1]]) as usize)
+        .ok_or(anyhow!("Invalid ICO file"))?;
+    if index >= count {
+        bail!("Page not found");
+    }
+
+    let start = ICO_HEADER_SIZE + index * ICO_ENTRY_SIZE;
+    let entry = data
+        .get(start..start + ICO_ENTRY_SIZE)
+        .ok_or(anyhow!("Invalid ICO file"))?;
+    let size = u32::from_le_bytes(entry[8..12].try_into()?) as usize;
+    let offset = u32::from_le_bytes(entry[12..16].try_into()?) as usize;
+    let image = offset
+        .checked_add(size)
+        .and_then(|end| data.get(offset..end))
+        .ok_or(anyhow!("Invalid ICO file"))?;
+
+    let mut icon = Vec::with_capacity(ICO_HEADER_SIZE + ICO_ENTRY_SIZE + size);
+    icon.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+    icon.extend_from_slice(&entry[..12]);
+    icon.extend_from_slice(&((ICO_HEADER_SIZE + ICO_ENTRY_SIZE) as u32).to_le_bytes());
+    icon.extend_from_slice(image);
+
+    Ok(ImageReader::with_format(Cursor::new(icon), ImageFormat::Ico).decode()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::ico::{IcoEncoder, IcoFrame};
+    use image::{ExtendedColorType, GenericImageView, Rgba};
+    use tiff::encoder::{TiffEncoder, colortype};
+
+    fn create_reader(bytes: Vec<u8>, format: ImageFormat) -> ImageReader<Cursor<Vec<u8>>> {
+        ImageReader::with_format(Cursor::new(bytes), format)
+    }
+
+    #[test]
+    fn test_decodes_tiff_page() {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+        for value in [50u8, 150] {
+            let data = vec![value; 4 * 2 * 3];
+            encoder.write_image::<colortype::RGB8>(4, 2, &data).unwrap();
+        }
+        let bytes = buffer.into_inner();
+
+        let image = decode_page(create_reader(bytes.clone(), ImageFormat::Tiff), 1).unwrap();
+        assert_eq!(image.dimensions(), (4, 2));
+        assert_eq!(image.get_pixel(0, 0), Rgba([150, 150, 150, 255]));
+
+        let image = decode_page(create_reader(bytes.clone(), ImageFormat::Tiff), 0).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Rgba([50, 50, 50, 255]));
+
+        assert!(decode_page(create_reader(bytes, ImageFormat::Tiff), 2).is_err());
+    }
+
+    /// Builds an uncompressed little-endian TIFF with a 1-bit page of 8x2 pixels for each value.
+    fn create_bilevel_tiff(pages: &[u8]) -> Vec<u8> {
+        let mut data = b"II\x2a\0".to_vec();
+        for (index, &value) in pages.iter().enumerate() {
+            let directory = data.len() as u32 + 4 + 2;
+            data.extend_from_slice(&directory.to_le_bytes());
+            data.extend_from_slice(&[value, value]);
+
+            let strip = directory - 2;
+            let entries: [(u16, u16, u32); 9] = [
+                (256, 3, 8),     // ImageWidth
+                (257, 3, 2),     // ImageLength
+                (258, 3, 1),     // BitsPerSample
+                (259, 3, 1),     // Compression: none
+                (262, 3, 1),     // PhotometricInterpretation: BlackIsZero
+                (273, 4, strip), // StripOffsets
+                (277, 3, 1),     // SamplesPerPixel
+                (278, 3, 2),     // RowsPerStrip
+                (279, 4, 2),     // StripByteCounts
+            ];
+            data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+            for (tag, field_type, value) in entries {
+                data.extend_from_slice(&tag.to_le_bytes());
+                data.extend_from_slice(&field_type.to_le_bytes());
+                data.extend_from_slice(&1u32.to_le_bytes());
+                if field_type == 3 {
+                    data.extend_from_slice(&(value as u16).to_le_bytes());
+                    data.extend_from_slice(&[0, 0]);
+                } else {
+                    data.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            if index + 1 == pages.len() {
+                data.extend_from_slice(&[0; 4]);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_decodes_bilevel_tiff_page() {
+        let bytes = create_bilevel_tiff(&[0x00, 0xf0, 0xff]);
+
+        let image = decode_page(create_reader(bytes.clone(), ImageFormat::Tiff), 1).unwrap();
+        assert_eq!(image.dimensions(), (8, 2));
+        assert_eq!(image.get_pixel(0, 1), Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(7, 1), Rgba([0, 0, 0, 255]));
+
+        let image = decode_page(create_reader(bytes.clone(), ImageFormat::Tiff), 2).unwrap();
+        assert_eq!(image.get_pixel(7, 0), Rgba([255, 255, 255, 255]));
+
+        assert!(decode_page(create_reader(bytes, ImageFormat::Tiff), 3).is_err());
+    }
+
+    #[test]
+    fn test_fails_on_looping_tiff_directories() {
+        let mut bytes = create_bilevel_tiff(&[0x00, 0xff]);
+        // Point the last directory back at the first one
+        let end = bytes.len();
+        bytes[end - 4..].copy_from_slice(&[10, 0, 0, 0]);
+        assert!(decode_page(create_reader(bytes, ImageFormat::Tiff), 5).is_err());
+    }
+
+    #[test]
+    fn test_decodes_ico_entry() {
+        let frames: Vec<_> = [(16, 200u8), (32, 100)]
+            .into_iter()
+            .map(|(size, value)| {
+                let data = vec![value; (size * size * 4) as usize];
+                IcoFrame::as_png(&data, size, size, ExtendedColorType::Rgba8).unwrap()
+            })
+            .collect();
+        let mut bytes = Vec::new();
+        IcoEncoder::new(&mut bytes).encode_images(&frames).unwrap();
+
+        let image = decode_page(create_reader(bytes.clone(), ImageFormat::Ico), 0).unwrap();
+        assert_eq!(image.dimensions(), (16, 16));
+        assert_eq!(image.get_pixel(0, 0), Rgba([200; 4]));
+
+        let image = decode_page(create_reader(bytes.clone(), ImageFormat::Ico), 1).unwrap();
+        assert_eq!(image.dimensions(), (32, 32));
+
+        assert!(decode_page(create_reader(bytes, ImageFormat::Ico), 2).is_err());
+    }
+
+    #[test]
+    fn test_fails_on_single_page_images() {
+        assert!(ensure_single_page(None).is_ok());
+        assert!(ensure_single_page(Some(0)).is_ok());
+        assert!(ensure_single_page(Some(1)).is_err());
+    }
+}
@@ -1,9 +1,16 @@
-use crate::operation::{Operation, Rotation};
+use crate::encode::Subsampling;
+use crate::operation::{
+    CropGravity, Gravity, Length, Operation, Overlay, RedactMode, Rotation, Text, Watermark,
+    WatermarkPosition,
+};
+use crate::text::MAX_TEXT_LENGTH;
+use crate::util::color::parse_hex_color;
 use anyhow::{anyhow, bail};
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::prelude::*;
-use image::{EncodableLayout, ImageFormat};
-use std::str;
+use image::{EncodableLayout, ImageFormat, Pixel};
+use std::ops::RangeInclusive;
+use std::str::{self, FromStr};
 
 pub struct Params {
     pub url: String,
@@ -27,17 +34,57 @@ pub fn parse_params(path_without_signature: &str) -> Result<Params, anyhow::Erro
             }
 
             ["speed", speed] => {
-                operations.push(Operation::Speed(speed.parse::<u8>()?));
+                operations.push(Operation::Speed(parse_in_range(speed, 1..=30)?));
             }
 
             ["quality", quality] => {
                 operations.push(Operation::Quality(quality.parse::<u8>()?));
             }
 
+            ["lossless", lossless] => {
+                operations.push(Operation::Lossless(parse_bool(lossless)?));
+            }
+
+            ["progressive", progressive] => {
+                operations.push(Operation::Progressive(parse_bool(progressive)?));
+            }
+
+            ["subsampling", subsampling] => {
+                operations.push(Operation::Subsampling(match *subsampling {
+                    "444" => Subsampling::Yuv444,
+                    "422" => Subsampling::Yuv422,
+                    "420" => Subsampling::Yuv420,
+                    _ => bail!("Invalid subsampling"),
+                }));
+            }
+
+            ["compression", compression] => {
+                operations.push(Operation::Compression(parse_in_range(compression, 0..=9)?));
+            }
+
+            ["colors", colors] => {
+                operations.push(Operation::Colors(parse_in_range(colors, 2..=256)?));
+            }
+
+            ["frame", frame] => {
+                operations.push(Operation::Frame(frame.parse::<usize>()?));
+            }
+
+            ["page", page] => {
+                operations.push(Operation::Page(page.parse::<usize>()?));
+            }
+
+            ["max_bytes", max_bytes] => {
+                operations.push(Operation::MaxBytes(parse_in_range(
+                    max_bytes,
+                    1..=usize::MAX,
+                )?));
+            }
+
             ["resize", width, height] => {
                 operations.push(Operation::Resize(
-                    width.parse::<u32>()?,
-                    height.parse::<u32>()?,
+                    parse_length(width)?,
+                    parse_length(height)?,
                 ));
             }
 
@@ -55,22 +102,259 @@ pub fn parse_params(path_without_signature: &str) -> Result<Params, anyhow::Erro
                 }
             }
 
+            ["background", color] => {
+                operations.push(Operation::Background(parse_hex_color(color)?.to_rgb()));
+            }
+
+            ["blur", sigma] => {
+                operations.push(Operation::Blur(parse_sigma(sigma)?));
+            }
+
+            ["sharpen", sigma] => {
+                operations.push(Operation::Sharpen(parse_sigma(sigma)?, 0));
+            }
+
+            ["sharpen", sigma, threshold] => {
+                operations.push(Operation::Sharpen(
+                    parse_sigma(sigma)?,
+                    threshold.parse::<u8>()?.into(),
+                ));
+            }
+
+            ["brightness", value] => {
+                operations.push(Operation::Brightness(parse_in_range(value, -255..=255)?));
+            }
+
+            ["contrast", value] => {
+                operations.push(Operation::Contrast(parse_in_range(value, -100.0..=100.0)?));
+            }
+
+            ["saturation", value] => {
+                operations.push(Operation::Saturation(parse_in_range(
+                    value,
+                    -100.0..=100.0,
+                )?));
+            }
+
+            ["hue", degrees] => {
+                operations.push(Operation::Hue(parse_in_range(degrees, -360..=360)?));
+            }
+
+            ["gamma", value] => {
+                operations.push(Operation::Gamma(parse_in_range(value, 0.1..=10.0)?));
+            }
+
+            ["grayscale"] => {
+                operations.push(Operation::Grayscale);
+            }
+
+            ["sepia"] => {
+                operations.push(Operation::Sepia);
+            }
+
+            ["invert"] => {
+                operations.push(Operation::Invert);
+            }
+
+            ["tint", color] => {
+                operations.push(Operation::Tint(parse_hex_color(color)?));
+            }
+
+            ["watermark", opacity, position, x_offset, y_offset, scale] => {
+                operations.push(Operation::Watermark(Watermark {
+                    opacity: parse_in_range(opacity, 0.0..=1.0)?,
+                    position: match *position {
+                        "repeat" => WatermarkPosition::Repeat,
+                        gravity => WatermarkPosition::Gravity(parse_gravity(gravity)?),
+                    },
+                    x_offset: x_offset.parse::<i32>()?,
+                    y_offset: y_offset.parse::<i32>()?,
+                    scale: parse_in_range(scale, 0.0..=1.0)?,
+                }));
+            }
+
+            ["overlay", encoded_url, gravity, x_offset, y_offset, opacity] => {
+                operations.push(Operation::Overlay(Overlay {
+                    url: decode_base64(encoded_url)?,
+                    gravity: parse_gravity(gravity)?,
+                    x_offset: x_offset.parse::<i32>()?,
+                    y_offset: y_offset.parse::<i32>()?,
+                    opacity: parse_in_range(opacity, 0.0..=1.0)?,
+                }));
+            }
+
+            ["text", encoded_text, size, color, gravity, font @ ..] if font.len() <= 1 => {
+                let text = decode_base64(encoded_text)?;
+                if text.chars().count() > MAX_TEXT_LENGTH {
+                    bail!("Text is too long");
+                }
+                operations.push(Operation::Text(Text {
+                    text,
+                    size: parse_in_range(size, 1.0..=1000.0)?,
+                    color: parse_hex_color(color)?,
+                    gravity: parse_gravity(gravity)?,
+                    font: font.first().map(|font| font.to_string()),
+                }));
+            }
+
+            ["radius", radius] => {
+                operations.push(Operation::Radius(parse_length(radius)?));
+            }
+
+            ["circle"] => {
+                operations.push(Operation::Circle);
+            }
+
+            ["trim", threshold] => {
+                operations.push(Operation::Trim(threshold.parse::<u8>()?, None));
+            }
+
+            ["trim", threshold, color] => {
+                operations.push(Operation::Trim(
+                    threshold.parse::<u8>()?,
+                    Some(parse_hex_color(color)?),
+                ));
+            }
+
+            ["gravity", "smart"] => {
+                operations.push(Operation::Gravity(CropGravity::Smart));
+            }
+
+            ["gravity", "fp", x, y] => {
+                operations.push(Operation::Gravity(CropGravity::FocalPoint(
+                    parse_in_range(x, 0.0..=1.0)?,
+                    parse_in_range(y, 0.0..=1.0)?,
+                )));
+            }
+
+            ["gravity", gravity] => {
+                operations.push(Operation::Gravity(CropGravity::Gravity(parse_gravity(
+                    gravity,
+                )?)));
+            }
+
+            ["crop", width, height] => {
+                operations.push(Operation::Crop(parse_length(width)?, parse_length(height)?));
+            }
+
+            ["fill", width, height] => {
+                operations.push(Operation::Fill(parse_length(width)?, parse_length(height)?));
+            }
+
+            ["ar", width, height] => {
+                operations.push(Operation::AspectRatio(
+                    parse_in_range(width, 1..=u32::MAX)?,
+                    parse_in_range(height, 1..=u32::MAX)?,
+                ));
+            }
+
+            ["pixelate", size] => {
+                operations.push(Operation::Pixelate(parse_in_range(size, 1..=1000)?));
+            }
+
+            ["redact", x, y, width, height, mode] => {
+                operations.push(Operation::Redact(
+                    x.parse::<u32>()?,
+                    y.parse::<u32>()?,
+                    width.parse::<u32>()?,
+                    height.parse::<u32>()?,
+                    match *mode {
+                        "pixelate" => RedactMode::Pixelate,
+                        "blur" => RedactMode::Blur,
+                        "fill" => RedactMode::Fill,
+                        _ => {
+                            bail!("Invalid redact mode");
+                        }
+                    },
+                ));
+            }
+
+            ["border", width, color, placement @ ..] if placement.len() <= 1 => {
+                operations.push(Operation::Border(
+                    parse_in_range(width, 0..=1000)?,
+                    parse_hex_color(color)?,
+                    match placement.first() {
+                        None | Some(&"outside") => false,
+                        Some(&"inside") => true,
+                        _ => {
+                            bail!("Invalid border placement");
+                        }
+                    },
+                ));
+            }
+
             _ => {
                 bail!("Invalid filter");
             }
         }
     }
 
-    let url = str::from_utf8(
-        URL_SAFE_NO_PAD
-            .decode(encoded_url.replace('=', ""))?
-            .as_bytes(),
-    )?
-    .to_string();
+    let url = decode_base64(encoded_url)?;
 
     Ok(Params { url, operations })
 }
 
+fn decode_base64(encoded: &str) -> anyhow::Result<String> {
+    Ok(str::from_utf8(URL_SAFE_NO_PAD.decode(encoded.replace('=', ""))?.as_bytes())?.to_string())
+}
+
+fn parse_bool(value: &str) -> anyhow::Result<bool> {
+    Ok(match value {
+        "1" | "true" => true,
+        "0" | "false" => false,
+        _ => {
+            bail!("Invalid boolean");
+        }
+    })
+}
+
+fn parse_gravity(gravity: &str) -> anyhow::Result<Gravity> {
+    Ok(match gravity {
+        "north" => Gravity::North,
+        "south" => Gravity::South,
+        "east" => Gravity::East,
+        "west" => Gravity::West,
+        "northeast" => Gravity::NorthEast,
+        "northwest" => Gravity::NorthWest,
+        "southeast" => Gravity::SouthEast,
+        "southwest" => Gravity::SouthWest,
+        "center" => Gravity::Center,
+        _ => {
+            bail!("Invalid gravity");
+        }
+    })
+}
+
+/// Parses a length in pixels, or in percent when suffixed with `p`.
+fn parse_length(length: &str) -> anyhow::Result<Length> {
+    Ok(match length.strip_suffix('p') {
+        Some(percent) => Length::Percent(parse_in_range(percent, 0.0..=1000.0)?),
+        None => Length::Pixels(length.parse::<u32>()?),
+    })
+}
+
+fn parse_in_range<T>(value: &str, range: RangeInclusive<T>) -> anyhow::Result<T>
+where
+    T: FromStr + PartialOrd,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = value.parse::<T>()?;
+    if !range.contains(&value) {
+        bail!("Value out of range");
+    }
+
+    Ok(value)
+}
+
+fn parse_sigma(sigma: &str) -> anyhow::Result<f32> {
+    let sigma = sigma.parse::<f32>()?;
+    if !(sigma > 0.0 && sigma <= 100.0) {
+        bail!("Invalid sigma");
+    }
+
+    Ok(sigma)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,6 +367,15 @@ mod tests {
         assert_eq!(result.operations.len(), 4);
     }
 
+    #[test]
+    fn test_parses_background() {
+        let result = parse_params("background:ff8000/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [Operation::Background(image::Rgb([255, 128, 0]))]
+        ));
+    }
+
     #[test]
     fn test_fails_parsing_due_to_missing_image_url() {
         let result = parse_params("resize:800:600");
@@ -107,6 +400,347 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_fails_parsing_due_to_invalid_color() {
+        let result = parse_params("background:xyz/cGF0aA");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parses_blur_and_sharpen() {
+        let result = parse_params("blur:1.5/sharpen:0.8/sharpen:1:10/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Blur(1.5),
+                Operation::Sharpen(0.8, 0),
+                Operation::Sharpen(1.0, 10)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_invalid_sigma() {
+        assert!(parse_params("blur:0/cGF0aA").is_err());
+        assert!(parse_params("blur:NaN/cGF0aA").is_err());
+        assert!(parse_params("sharpen:1000/cGF0aA").is_err());
+        assert!(parse_params("sharpen:0:10/cGF0aA").is_err());
+        assert!(parse_params("blur:100/cGF0aA").is_ok());
+    }
+
+    #[test]
+    fn test_parses_color_adjustments() {
+        let result =
+            parse_params("brightness:-20/contrast:15.5/saturation:-100/hue:180/gamma:2.2/cGF0aA")
+                .unwrap();
+        assert_eq!(result.operations.len(), 5);
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_out_of_range_adjustment() {
+        assert!(parse_params("brightness:300/cGF0aA").is_err());
+        assert!(parse_params("contrast:-101/cGF0aA").is_err());
+        assert!(parse_params("saturation:NaN/cGF0aA").is_err());
+        assert!(parse_params("hue:720/cGF0aA").is_err());
+        assert!(parse_params("gamma:0/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_filters() {
+        let result = parse_params("grayscale/sepia/invert/tint:ff000080/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Grayscale,
+                Operation::Sepia,
+                Operation::Invert,
+                Operation::Tint(image::Rgba([255, 0, 0, 128]))
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_parses_watermark() {
+        let result =
+            parse_params("watermark:0.5:southeast:10:20:0.25/watermark:1:repeat:0:0:0/cGF0aA")
+                .unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Watermark(Watermark {
+                    position: WatermarkPosition::Gravity(Gravity::SouthEast),
+                    x_offset: 10,
+                    y_offset: 20,
+                    ..
+                }),
+                Operation::Watermark(Watermark {
+                    position: WatermarkPosition::Repeat,
+                    ..
+                })
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_invalid_watermark() {
+        assert!(parse_params("watermark:2:center:0:0:0/cGF0aA").is_err());
+        assert!(parse_params("watermark:1:middle:0:0:0/cGF0aA").is_err());
+        assert!(parse_params("watermark:1:center:0:0/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_overlay() {
+        let result = parse_params("overlay:b3ZlcmxheQ:northeast:4:8:0.75/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [Operation::Overlay(Overlay {
+                gravity: Gravity::NorthEast,
+                x_offset: 4,
+                y_offset: 8,
+                ..
+            })]
+        ));
+        let [Operation::Overlay(overlay)] = result.operations.as_slice() else {
+            unreachable!();
+        };
+        assert_eq!(overlay.url, "overlay");
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_invalid_overlay_url() {
+        let result = parse_params("overlay:!!!:center:0:0:1/cGF0aA");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parses_text() {
+        let result =
+            parse_params("text:SGVsbG8:48:ffffff:south/text:SGk:12:000:center:serif/cGF0aA")
+                .unwrap();
+        let [Operation::Text(first), Operation::Text(second)] = result.operations.as_slice() else {
+            panic!("expected two text operations");
+        };
+        assert_eq!(first.text, "Hello");
+        assert_eq!(first.size, 48.0);
+        assert_eq!(first.gravity, Gravity::South);
+        assert_eq!(first.font, None);
+        assert_eq!(second.text, "Hi");
+        assert_eq!(second.color, image::Rgba([0, 0, 0, 255]));
+        assert_eq!(second.font.as_deref(), Some("serif"));
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_invalid_text() {
+        assert!(parse_params("text:SGVsbG8:0:ffffff:south/cGF0aA").is_err());
+        assert!(parse_params("text:SGVsbG8:12:ffffff:south:serif:extra/cGF0aA").is_err());
+        let text = URL_SAFE_NO_PAD.encode("a".repeat(MAX_TEXT_LENGTH + 1));
+        assert!(parse_params(&format!("text:{text}:12:ffffff:south/cGF0aA")).is_err());
+    }
+
+    #[test]
+    fn test_parses_masks() {
+        let result = parse_params("radius:16/radius:50p/circle/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Radius(Length::Pixels(16)),
+                Operation::Radius(Length::Percent(50.0)),
+                Operation::Circle
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_invalid_length() {
+        assert!(parse_params("radius:-4/cGF0aA").is_err());
+        assert!(parse_params("radius:1500p/cGF0aA").is_err());
+        assert!(parse_params("radius:p/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_trim() {
+        let result = parse_params("trim:10/trim:0:ffffff/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Trim(10, None),
+                Operation::Trim(0, Some(image::Rgba([255, 255, 255, 255])))
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_parses_crop_and_fill() {
+        let result =
+            parse_params("gravity:smart/crop:100:50/gravity:north/fill:20:30/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Gravity(CropGravity::Smart),
+                Operation::Crop(Length::Pixels(100), Length::Pixels(50)),
+                Operation::Gravity(CropGravity::Gravity(Gravity::North)),
+                Operation::Fill(Length::Pixels(20), Length::Pixels(30))
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_parses_focal_point() {
+        let result = parse_params("gravity:fp:0.25:0.8/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [Operation::Gravity(CropGravity::FocalPoint(0.25, 0.8))]
+        ));
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_invalid_gravity() {
+        assert!(parse_params("gravity:up/cGF0aA").is_err());
+        assert!(parse_params("gravity:fp:1.5:0.5/cGF0aA").is_err());
+        assert!(parse_params("gravity:fp:0.5/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_pixelate_and_redact() {
+        let result = parse_params("pixelate:12/redact:10:20:30:40:blur/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Pixelate(12),
+                Operation::Redact(10, 20, 30, 40, RedactMode::Blur)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_invalid_redact_mode() {
+        let result = parse_params("redact:0:0:10:10:erase/cGF0aA");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parses_border() {
+        let result = parse_params("border:4:000/border:2:ffffff:inside/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Border(4, image::Rgba([0, 0, 0, 255]), false),
+                Operation::Border(2, image::Rgba([255, 255, 255, 255]), true)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_invalid_border_placement() {
+        let result = parse_params("border:4:000:around/cGF0aA");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parses_relative_sizes() {
+        let result = parse_params("resize:50p:50p/crop:100:25p/ar:16:9/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Resize(Length::Percent(50.0), Length::Percent(50.0)),
+                Operation::Crop(Length::Pixels(100), Length::Percent(25.0)),
+                Operation::AspectRatio(16, 9)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_parses_sizes_above_full_size() {
+        let result = parse_params("resize:250p:1000p/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [Operation::Resize(
+                Length::Percent(250.0),
+                Length::Percent(1000.0)
+            )]
+        ));
+        assert!(parse_params("resize:1001p:50p/cGF0aA").is_err());
+        assert!(parse_params("fill:-10p:50p/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_fails_parsing_due_to_invalid_aspect_ratio() {
+        assert!(parse_params("ar:16:0/cGF0aA").is_err());
+        assert!(parse_params("ar:16/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_lossless() {
+        let result = parse_params("lossless:1/lossless:false/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [Operation::Lossless(true), Operation::Lossless(false)]
+        ));
+        assert!(parse_params("lossless:yes/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_jpeg_options() {
+        let result = parse_params("progressive:1/subsampling:422/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [
+                Operation::Progressive(true),
+                Operation::Subsampling(Subsampling::Yuv422)
+            ]
+        ));
+        assert!(parse_params("subsampling:411/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_png_options() {
+        let result = parse_params("compression:9/colors:256/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [Operation::Compression(9), Operation::Colors(256)]
+        ));
+        assert!(parse_params("compression:10/cGF0aA").is_err());
+        assert!(parse_params("colors:1/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_max_bytes() {
+        let result = parse_params("max_bytes:50000/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [Operation::MaxBytes(50000)]
+        ));
+        assert!(parse_params("max_bytes:0/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_speed() {
+        let result = parse_params("speed:30/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [Operation::Speed(30)]
+        ));
+        assert!(parse_params("speed:0/cGF0aA").is_err());
+        assert!(parse_params("speed:31/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_frame() {
+        let result = parse_params("frame:2/cGF0aA").unwrap();
+        assert!(matches!(
+            result.operations.as_slice(),
+            [Operation::Frame(2)]
+        ));
+        assert!(parse_params("frame:-1/cGF0aA").is_err());
+    }
+
+    #[test]
+    fn test_parses_page() {
+        let result = parse_params("page:3/cGF0aA").unwrap();
+        assert!(matches!(result.operations.as_slice(), [Operation::Page(3)]));
+        assert!(parse_params("page:x/cGF0aA").is_err());
+    }
+
     #[test]
     fn test_fails_parsing_due_to_invalid_number() {
         let result = parse_params("quality:high/cGF0aA");
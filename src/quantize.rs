@@ -0,0 +1,78 @@
+use color_quant::NeuQuant;
+use image::RgbaImage;
+
+/// Image reduced to a palette of at most 256 colors.
+pub struct IndexedImage {
+    /// RGBA palette entries, four bytes per color
+    pub palette: Vec<u8>,
+    /// One palette index per pixel
+    pub indices: Vec<u8>,
+}
+
+/// Reduces the image to at most `colors` colors with Floyd-Steinberg dithering.
+pub fn quantize(image: &RgbaImage, colors: u16) -> IndexedImage {
+    let colors = colors.clamp(2, 256) as usize;
+    let quantizer = NeuQuant::new(10, colors, image.as_raw());
+    let palette = quantizer.color_map_rgba();
+
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut indices = Vec::with_capacity(width * height);
+    // Error diffused into the current and next row, one extra pixel on each side
+    let mut errors = vec![[0f32; 4]; width + 2];
+    let mut next_errors = vec![[0f32; 4]; width + 2];
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = image.get_pixel(x as u32, y as u32).0;
+            let mut value = [0u8; 4];
+            for channel in 0..4 {
+                value[channel] =
+                    (pixel[channel] as f32 + errors[x + 1][channel]).clamp(0.0, 255.0) as u8;
+            }
+
+            let index = quantizer.index_of(&value);
+            indices.push(index as u8);
+
+            let color = &palette[index * 4..index * 4 + 4];
+            for channel in 0..4 {
+                let error = value[channel] as f32 - color[channel] as f32;
+                errors[x + 2][channel] += error * 7.0 / 16.0;
+                next_errors[x][channel] += error * 3.0 / 16.0;
+                next_errors[x + 1][channel] += error * 5.0 / 16.0;
+                next_errors[x + 2][channel] += error / 16.0;
+            }
+        }
+
+        errors = std::mem::replace(&mut next_errors, vec![[0f32; 4]; width + 2]);
+    }
+
+    IndexedImage { palette, indices }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_quantizes_to_palette() {
+        let image = RgbaImage::from_fn(32, 32, |x, y| {
+            Rgba([
+                (x * 8) as u8,
+                (y * 8) as u8,
+                128,
+                if x < 16 { 255 } else { 0 },
+            ])
+        });
+
+        let result = quantize(&image, 16);
+        assert_eq!(result.palette.len(), 16 * 4);
+        assert_eq!(result.indices.len(), 32 * 32);
+        assert!(result.indices.iter().all(|&index| index < 16));
+
+        let alpha =
+            |x: usize, y: usize| result.palette[result.indices[y * 32 + x] as usize * 4 + 3];
+        assert_eq!(alpha(0, 0), 255);
+        assert_eq!(alpha(31, 31), 0);
+    }
+}
@@ -1,15 +1,21 @@
 use crate::AppState;
-use crate::encode::encode_image;
-use crate::fetcher::Fetcher;
-use crate::operation::apply_operations;
+use crate::animation::{decode_frames, select_still, supports_animation};
+use crate::decode::{decode_jxl, decode_pcx};
+use crate::encode::encode_frames;
+use crate::fetcher::{FetchResult, Fetcher};
+use crate::operation::{self, Assets, Operation, apply_operations_to_frames};
+use crate::page::{decode_page, ensure_single_page};
 use crate::params::parse_params;
 use crate::signature::verify_signature;
+use crate::svg;
 use crate::util::error::AppError;
-use crate::util::format;
+use crate::util::format::{self, InputFormat};
+use axum::body::Bytes;
 use axum::extract::{Path, State};
 use axum::http::{HeaderMap, HeaderValue};
 use axum::response::IntoResponse;
-use image::ImageReader;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use std::collections::HashMap;
 use std::io::Cursor;
 use tokio::task;
 
@@ -25,48 +31,146 @@ pub async fn process(
     let params = parse_params(rest.as_str())
         .map_err(|_| AppError::UnprocessableEntity("Invalid params".to_string()))?;
 
+    if state.watermark.is_none()
+        && params
+            .operations
+            .iter()
+            .any(|operation| matches!(operation, Operation::Watermark(_)))
+    {
+        return Err(AppError::UnprocessableEntity(
+            "Watermark is not configured".to_string(),
+        ));
+    }
+
+    for operation in &params.operations {
+        if let Operation::Text(text) = operation
+            && state.fonts.get(text.font.as_deref()).is_none()
+        {
+            return Err(AppError::UnprocessableEntity("Font not found".to_string()));
+        }
+    }
+
     // Fetch remote image
-    let fetcher =
-        state
-            .resolve_fetcher(params.url.as_str())
-            .ok_or(AppError::UnprocessableEntity(
-                "Unsupported protocol for remote image".to_string(),
-            ))?;
-    let fetch_result = fetcher
-        .fetch(params.url.as_str())
-        .await
-        .map_err(|_| AppError::NotFound("Fetching remote image failed".to_string()))?;
-
-    // Create reader for appropriate image format
-    let reader = if let Some(image_format) = fetch_result.image_format {
-        let mut reader = ImageReader::new(Cursor::new(fetch_result.bytes));
-        reader.set_format(image_format);
-        reader
-    } else {
-        ImageReader::new(Cursor::new(fetch_result.bytes))
-            .with_guessed_format()
-            .map_err(|_| {
-                AppError::UnprocessableEntity("Unable to determine image format".to_string())
-            })?
+    let fetch_result = fetch_image(&state, params.url.as_str(), "remote image").await?;
+    let filename = fetch_result.filename.clone();
+
+    // Create reader for appropriate image format, SVGs are rasterized and output as PNG by default,
+    // JPEG XL images are output as JPEG, or PNG once decoded if they have alpha, and PCX as PNG
+    let source = create_source(fetch_result)?;
+    let input_format = match source {
+        Source::Image(ref reader) => reader.format().ok_or(AppError::UnprocessableEntity(
+            "Unable to determine image format".to_string(),
+        ))?,
+        Source::Svg(_) => ImageFormat::Png,
+        Source::Jxl(_) => ImageFormat::Jpeg,
+        Source::Pcx(_) => ImageFormat::Png,
     };
-    let input_format = reader.format().ok_or(AppError::UnprocessableEntity(
-        "Unable to determine image format".to_string(),
-    ))?;
+
+    // SVGs are served as vectors when nothing needs them rasterized
+    if let Source::Svg(ref bytes) = source
+        && svg::is_passthrough(&params.operations)
+    {
+        let buffer = svg::sanitize(bytes)
+            .map_err(|_| AppError::UnprocessableEntity("Invalid SVG".to_string()))?;
+        let mut headers = response_headers(filename.as_deref(), "image/svg+xml");
+        // Blocks anything the sanitizer missed when the SVG is opened directly
+        headers.insert(
+            "Content-Security-Policy",
+            HeaderValue::from_static(
+                "default-src 'none'; style-src 'unsafe-inline'; img-src data:",
+            ),
+        );
+        return Ok((headers, buffer));
+    }
+
+    // Fetch overlay images, which are covered by the signature like the rest of the path
+    let mut overlay_sources = HashMap::new();
+    for operation in &params.operations {
+        if let Operation::Overlay(overlay) = operation {
+            if overlay_sources.contains_key(&overlay.url) {
+                continue;
+            }
+
+            let fetch_result = fetch_image(&state, overlay.url.as_str(), "overlay image").await?;
+            overlay_sources.insert(overlay.url.clone(), create_source(fetch_result)?);
+        }
+    }
+
+    let watermark = state.watermark.clone();
+    let fonts = state.fonts.clone();
+    let frame = params
+        .operations
+        .iter()
+        .rev()
+        .find_map(|operation| match *operation {
+            Operation::Frame(frame) => Some(frame),
+            _ => None,
+        })
+        // Only the first frame is kept when the output cannot animate, so skip decoding the rest
+        .or_else(|| {
+            let format = operation::output_format(input_format, &params.operations);
+            (!supports_animation(format)).then_some(0)
+        });
+    let page = params
+        .operations
+        .iter()
+        .rev()
+        .find_map(|operation| match *operation {
+            Operation::Page(page) => Some(page),
+            _ => None,
+        });
 
     // Decode, apply operations and encode
     let join = task::spawn_blocking(move || {
-        let decoded_image = reader.decode()?;
+        let mut input_format = input_format;
+        if !matches!(source, Source::Image(_)) {
+            ensure_single_page(page)?;
+        }
+        let frames = match source {
+            Source::Image(reader) => match page {
+                Some(page) => select_still(decode_page(reader, page)?, frame)?,
+                None => decode_frames(reader, frame)?,
+            },
+            Source::Svg(bytes) => select_still(svg::rasterize(&bytes, &params.operations)?, frame)?,
+            Source::Jxl(bytes) => {
+                let image = decode_jxl(&bytes)?;
+                if image.color().has_alpha() {
+                    input_format = ImageFormat::Png;
+                }
+                select_still(image, frame)?
+            }
+            Source::Pcx(bytes) => select_still(decode_pcx(&bytes)?, frame)?,
+        };
 
-        let (image, output_options) =
-            apply_operations(decoded_image, input_format, params.operations.as_slice());
+        let mut assets = Assets {
+            watermark,
+            fonts,
+            ..Default::default()
+        };
+        for (url, source) in overlay_sources {
+            assets.overlays.insert(url, source.decode()?);
+        }
 
-        encode_image(image, output_options)
+        let (frames, output_options) = apply_operations_to_frames(
+            frames,
+            input_format,
+            params.operations.as_slice(),
+            &assets,
+        )?;
+
+        encode_frames(frames, output_options)
     });
     let (buffer, format) = join
         .await
         .map_err(|_| AppError::UnprocessableEntity("Processing image failed".to_string()))?
         .map_err(|_| AppError::UnprocessableEntity("Decoding image failed".to_string()))?;
 
+    let headers = response_headers(filename.as_deref(), format::resolve_content_type(format));
+
+    Ok((headers, buffer.into_inner()))
+}
+
+fn response_headers(filename: Option<&str>, content_type: &'static str) -> HeaderMap {
     let mut headers = HeaderMap::new();
     headers.insert(
         "Cache-Control",
@@ -74,17 +178,87 @@ pub async fn process(
     );
     headers.insert(
         "Content-Disposition",
-        fetch_result
-            .filename
-            .as_deref()
+        filename
             .map(|filename| format!("inline; filename=\"{}\"", filename))
             .and_then(|value| HeaderValue::from_str(&value).ok())
             .unwrap_or_else(|| HeaderValue::from_static("inline")),
     );
-    headers.insert(
-        "Content-Type",
-        HeaderValue::from_static(format::resolve_content_type(format)),
-    );
+    headers.insert("Content-Type", HeaderValue::from_static(content_type));
+    headers
+}
 
-    Ok((headers, buffer.into_inner()))
+async fn fetch_image(
+    state: &AppState,
+    url: &str,
+    description: &str,
+) -> Result<FetchResult, AppError> {
+    let fetcher = state
+        .resolve_fetcher(url)
+        .ok_or(AppError::UnprocessableEntity(format!(
+            "Unsupported protocol for {description}"
+        )))?;
+
+    fetcher
+        .fetch(url)
+        .await
+        .map_err(|_| AppError::NotFound(format!("Fetching {description} failed")))
+}
+
+/// Fetched image, either readable by `image`, an SVG document to rasterize, or a JPEG XL or PCX
+/// image.
+enum Source {
+    Image(ImageReader<Cursor<Bytes>>),
+    Svg(Bytes),
+    Jxl(Bytes),
+    Pcx(Bytes),
+}
+
+impl Source {
+    /// Decodes the first frame, rasterizing SVGs at their intrinsic size.
+    fn decode(self) -> anyhow::Result<DynamicImage> {
+        Ok(match self {
+            Source::Image(reader) => reader.decode()?,
+            Source::Svg(bytes) => svg::rasterize(&bytes, &[])?,
+            Source::Jxl(bytes) => decode_jxl(&bytes)?,
+            Source::Pcx(bytes) => decode_pcx(&bytes)?,
+        })
+    }
+}
+
+fn create_source(fetch_result: FetchResult) -> Result<Source, AppError> {
+    match fetch_result.image_format {
+        Some(InputFormat::Svg) => Ok(Source::Svg(fetch_result.bytes)),
+        Some(InputFormat::Jxl) => Ok(Source::Jxl(fetch_result.bytes)),
+        Some(InputFormat::Pcx) => Ok(Source::Pcx(fetch_result.bytes)),
+        Some(InputFormat::Image(image_format)) => {
+            let mut reader = ImageReader::new(Cursor::new(fetch_result.bytes));
+            reader.set_format(image_format);
+            Ok(Source::Image(reader))
+        }
+        None => {
+            // Magic bytes are more reliable than sniffing for markup, so formats known to `image`
+            // come first
+            let reader = ImageReader::new(Cursor::new(fetch_result.bytes))
+                .with_guessed_format()
+                .map_err(|_| {
+                    AppError::UnprocessableEntity("Unable to determine image format".to_string())
+                })?;
+            if reader.format().is_some() {
+                return Ok(Source::Image(reader));
+            }
+
+            let bytes = reader.into_inner().into_inner();
+            if format::is_svg(&bytes) {
+                Ok(Source::Svg(bytes))
+            } else if format::is_jxl(&bytes) {
+                Ok(Source::Jxl(bytes))
+            } else if format::is_pcx(&bytes) {
+                Ok(Source::Pcx(bytes))
+            } else {
+                Err(AppError::UnprocessableEntity(
+                    "Unable to determine image format".to_string(),
+                ))
+            }
+        }
+    }
 }
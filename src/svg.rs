@@ -0,0 +1,588 @@
+use crate::operation::{Length, Operation};
+use anyhow::{anyhow, bail};
+use flate2::read::GzDecoder;
+use image::{DynamicImage, Rgba, RgbaImage};
+use quick_xml::escape::unescape;
+use quick_xml::events::{BytesCData, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::name::{Namespace, ResolveResult};
+use quick_xml::reader::NsReader;
+use quick_xml::{Reader, Writer};
+use resvg::{tiny_skia, usvg};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+use std::str;
+
+/// Largest width or height an SVG is rasterized at.
+const MAX_SIZE: f32 = 10_000.0;
+
+/// Largest size a compressed SVG (`.svgz`) is decompressed to, or any SVG grows to once its
+/// entities are expanded.
+const MAX_DECOMPRESSED_SIZE: u64 = 32 * 1024 * 1024;
+
+const SVG_NAMESPACE: &[u8] = b"http://www.w3.org/2000/svg";
+const XLINK_NAMESPACE: &[u8] = b"http://www.w3.org/1999/xlink";
+const XML_NAMESPACE: &[u8] = b"http://www.w3.org/XML/1998/namespace";
+
+/// SVG elements kept when sanitizing. Everything else, including `script`, `foreignObject` and
+/// elements from other namespaces such as XHTML, is removed along with its children.
+const ALLOWED_ELEMENTS: &str = "\
+    a animate animateMotion animateTransform circle clipPath defs desc ellipse feBlend \
+    feColorMatrix feComponentTransfer feComposite feConvolveMatrix feDiffuseLighting \
+    feDisplacementMap feDistantLight feDropShadow feFlood feFuncA feFuncB feFuncG feFuncR \
+    feGaussianBlur feImage feMerge feMergeNode feMorphology feOffset fePointLight \
+    feSpecularLighting feSpotLight feTile feTurbulence filter g image line linearGradient marker \
+    mask metadata mpath path pattern polygon polyline radialGradient rect set stop style svg \
+    switch symbol text textPath title tspan use view";
+
+/// Attributes without a namespace kept when sanitizing, covering geometry, presentation,
+/// gradient, filter and animation attributes. Event handlers and anything unknown are removed.
+const ALLOWED_ATTRIBUTES: &str = "\
+    accumulate additive alignment-baseline amplitude attributeName attributeType azimuth \
+    baseFrequency baseline-shift baseProfile begin bias by calcMode class clip clip-path clip-rule \
+    clipPathUnits color color-interpolation color-interpolation-filters color-rendering cursor cx \
+    cy d diffuseConstant direction display divisor dominant-baseline dur dx dy edgeMode elevation \
+    end exponent fill fill-opacity fill-rule filter filterUnits flood-color flood-opacity font \
+    font-family font-size font-size-adjust font-stretch font-style font-variant font-weight fr \
+    from fx fy gradientTransform gradientUnits height href id image-rendering in in2 intercept k1 \
+    k2 k3 k4 kernelMatrix kernelUnitLength keyPoints keySplines keyTimes lang lengthAdjust \
+    letter-spacing lighting-color limitingConeAngle marker marker-end marker-mid marker-start \
+    markerHeight markerUnits markerWidth mask mask-type maskContentUnits maskUnits max media \
+    method min mode numOctaves offset opacity operator order orient overflow paint-order path \
+    pathLength patternContentUnits patternTransform patternUnits pointer-events points pointsAtX \
+    pointsAtY pointsAtZ preserveAlpha preserveAspectRatio primitiveUnits r radius refX refY \
+    repeatCount repeatDur requiredExtensions requiredFeatures restart result rotate rx ry scale \
+    seed shape-rendering side slope spacing specularConstant specularExponent spreadMethod \
+    startOffset stdDeviation stitchTiles stop-color stop-opacity stroke stroke-dasharray \
+    stroke-dashoffset stroke-linecap stroke-linejoin stroke-miterlimit stroke-opacity stroke-width \
+    style surfaceScale systemLanguage tableValues targetX targetY text-anchor text-decoration \
+    text-rendering textLength to transform transform-origin type unicode-bidi values vector-effect \
+    version viewBox visibility width word-spacing writing-mode x xChannelSelector x1 x2 y \
+    yChannelSelector y1 y2 z";
+
+/// Whether an SVG can be served as is, which is the case when no operation needs it rasterized.
+pub fn is_passthrough(operations: &[Operation]) -> bool {
+    operations.iter().all(|operation| {
+        matches!(
+            operation,
+            Operation::Speed(_)
+                | Operation::Quality(_)
+                | Operation::Lossless(_)
+                | Operation::Progressive(_)
+                | Operation::Subsampling(_)
+                | Operation::Compression(_)
+                | Operation::Colors(_)
+                | Operation::MaxBytes(_)
+                | Operation::Gravity(_)
+        )
+    })
+}
+
+/// Rewrites an SVG document with only allowed SVG elements and attributes, without scripts, event
+/// handlers, other namespaces and references to anything outside the document, so it can be
+/// served without running code or loading resources.
+pub fn sanitize(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let data = decompress(data)?;
+    let text = expand_entities(str::from_utf8(&data)?)?;
+    let mut reader = NsReader::from_str(&text);
+    let mut writer = Writer::new(Vec::new());
+    let mut has_root = false;
+
+    loop {
+        match reader.read_resolved_event()? {
+            (_, Event::Eof) => break,
+            (_, Event::Decl(declaration)) => writer.write_event(Event::Decl(declaration))?,
+            (_, Event::Comment(_) | Event::PI(_) | Event::DocType(_)) => {}
+            (namespace, Event::Start(element)) => {
+                check_root(&namespace, &element, &mut has_root)?;
+                if !is_allowed(&namespace, &element) {
+                    reader.read_to_end(element.name())?;
+                    continue;
+                }
+
+                if element.local_name().as_ref() == b"style" {
+                    let css = reader.read_text(element.name())?;
+                    if let Some(css) = sanitize_style(&css) {
+                        let name = str::from_utf8(element.name().into_inner())?.to_string();
+                        writer.write_event(Event::Start(sanitize_element(&reader, &element)?))?;
+                        writer.write_event(css)?;
+                        writer.write_event(Event::End(BytesEnd::new(name)))?;
+                    }
+                    continue;
+                }
+
+                writer.write_event(Event::Start(sanitize_element(&reader, &element)?))?;
+            }
+            (namespace, Event::Empty(element)) => {
+                check_root(&namespace, &element, &mut has_root)?;
+                if is_allowed(&namespace, &element) {
+                    writer.write_event(Event::Empty(sanitize_element(&reader, &element)?))?;
+                }
+            }
+            (_, Event::End(element)) => writer.write_event(Event::End(element))?,
+            (_, Event::Text(text)) => {
+                let text = text.unescape()?;
+                writer.write_event(Event::Text(BytesText::new(&text)))?;
+            }
+            (_, Event::CData(data)) => writer.write_event(Event::CData(data))?,
+        }
+    }
+
+    if !has_root {
+        bail!("Not an SVG document");
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Decompresses gzipped SVGs, failing if they expand past the size limit.
+fn decompress(data: &[u8]) -> anyhow::Result<Cow<'_, [u8]>> {
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Cow::Borrowed(data));
+    }
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(data)
+        .take(MAX_DECOMPRESSED_SIZE + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() as u64 > MAX_DECOMPRESSED_SIZE {
+        bail!("SVG is too large");
+    }
+
+    Ok(Cow::Owned(decompressed))
+}
+
+/// Whether the element is in the SVG namespace, or in none as with SVGs lacking `xmlns`.
+fn is_svg_namespace(namespace: &ResolveResult) -> bool {
+    match namespace {
+        ResolveResult::Bound(Namespace(namespace)) => *namespace == SVG_NAMESPACE,
+        ResolveResult::Unbound => true,
+        ResolveResult::Unknown(_) => false,
+    }
+}
+
+fn check_root(
+    namespace: &ResolveResult,
+    element: &BytesStart,
+    has_root: &mut bool,
+) -> anyhow::Result<()> {
+    let is_root = is_svg_namespace(namespace) && element.local_name().as_ref() == b"svg";
+    if !*has_root && !is_root {
+        bail!("Not an SVG document");
+    }
+    *has_root = true;
+    Ok(())
+}
+
+fn is_allowed(namespace: &ResolveResult, element: &BytesStart) -> bool {
+    let name = element.local_name();
+    if !is_svg_namespace(namespace)
+        || !ALLOWED_ELEMENTS
+            .split_whitespace()
+            .any(|allowed| allowed.as_bytes() == name.as_ref())
+    {
+        return false;
+    }
+
+    // Animations could set links or event handlers that were removed from the element itself
+    !matches!(name.as_ref(), b"set" | b"animate")
+        || !element.attributes().flatten().any(|attribute| {
+            if attribute.key.local_name().as_ref() != b"attributeName" {
+                return false;
+            }
+            let target = String::from_utf8_lossy(&attribute.value).to_ascii_lowercase();
+            let target = target.rsplit(':').next().unwrap_or_default().trim();
+            target == "href" || target.starts_with("on")
+        })
+}
+
+/// Copies the element with only allowed attributes, dropping links and values that reference
+/// other documents.
+fn sanitize_element(
+    reader: &NsReader<&[u8]>,
+    element: &BytesStart,
+) -> anyhow::Result<BytesStart<'static>> {
+    let mut sanitized = BytesStart::new(str::from_utf8(element.name().as_ref())?.to_string());
+    for attribute in element.attributes() {
+        let attribute = attribute?;
+        let key = str::from_utf8(attribute.key.as_ref())?;
+        let value = attribute.unescape_value()?;
+
+        let allowed = if attribute.key.as_namespace_binding().is_some() {
+            // Only keep declarations of namespaces whose elements and attributes are kept
+            [SVG_NAMESPACE, XLINK_NAMESPACE].contains(&value.as_bytes())
+        } else {
+            let (namespace, name) = reader.resolve_attribute(attribute.key);
+            let name = name.as_ref();
+            match namespace {
+                ResolveResult::Unbound if name == b"href" => is_local_link(&value),
+                // Animated values have no use for URLs, which could end up in other attributes
+                ResolveResult::Unbound if matches!(name, b"values" | b"from" | b"to" | b"by") => {
+                    !value.contains(':') && !has_external_reference(&value)
+                }
+                ResolveResult::Unbound => {
+                    ALLOWED_ATTRIBUTES
+                        .split_whitespace()
+                        .any(|allowed| allowed.as_bytes() == name)
+                        && !has_external_reference(&value)
+                }
+                ResolveResult::Bound(Namespace(XLINK_NAMESPACE)) if name == b"href" => {
+                    is_local_link(&value)
+                }
+                ResolveResult::Bound(Namespace(XML_NAMESPACE)) => {
+                    matches!(name, b"space" | b"lang")
+                }
+                _ => false,
+            }
+        };
+        if allowed {
+            sanitized.push_attribute((key, value.as_ref()));
+        }
+    }
+
+    Ok(sanitized)
+}
+
+/// Whether a link points within the document or embeds an image, rather than loading anything.
+fn is_local_link(value: &str) -> bool {
+    let value = value.trim_start().to_ascii_lowercase();
+    value.starts_with('#') || value.starts_with("data:image/")
+}
+
+/// Whether CSS loads anything outside the document. Escapes are treated as external since they
+/// could hide a `url(`.
+fn has_external_reference(css: &str) -> bool {
+    let css = css.to_ascii_lowercase();
+    if css.contains("@import") || css.contains('\\') {
+        return true;
+    }
+
+    css.split("url(").skip(1).any(|argument| {
+        let argument = argument.trim_start();
+        let argument = argument
+            .strip_prefix(['"', '\''])
+            .unwrap_or(argument)
+            .trim_start();
+        !argument.starts_with('#')
+    })
+}
+
+/// Contents of a `style` element to write back, which must be plain text or a single CDATA
+/// section so no elements can be smuggled inside it. Text is checked once its character
+/// references are decoded, as browsers would, and is dropped if it references anything outside
+/// the document.
+fn sanitize_style(raw: &str) -> Option<Event<'_>> {
+    let trimmed = raw.trim();
+    if let Some(css) = trimmed
+        .strip_prefix("<![CDATA[")
+        .and_then(|css| css.strip_suffix("]]>"))
+        .filter(|css| !css.contains("]]>"))
+    {
+        return (!has_external_reference(css)).then(|| Event::CData(BytesCData::new(css)));
+    }
+
+    if raw.contains('<') {
+        return None;
+    }
+    let css = unescape(raw).ok()?;
+    (!has_external_reference(&css)).then(|| Event::Text(BytesText::new(&css).into_owned()))
+}
+
+/// Parses internal entities declared in a doctype, such as those exported by Illustrator.
+fn parse_entities(doctype: &str) -> HashMap<String, String> {
+    doctype
+        .split("<!ENTITY")
+        .skip(1)
+        .filter_map(|declaration| {
+            let (name, rest) = declaration.trim_start().split_once(char::is_whitespace)?;
+            let rest = rest.trim_start();
+            let quote = rest
+                .chars()
+                .next()
+                .filter(|&quote| quote == '"' || quote == '\'')?;
+            let (value, _) = rest[1..].split_once(quote)?;
+            (!value.contains(['<', '&', '%', '"', '\'']))
+                .then(|| (name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Replaces references to internal entities declared in the doctype with their values, since
+/// they can be used anywhere including namespace declarations. Values with markup, quotes or
+/// references to other entities are skipped.
+fn expand_entities(text: &str) -> anyhow::Result<Cow<'_, str>> {
+    let mut reader = Reader::from_str(text);
+    let doctype = loop {
+        match reader.read_event()? {
+            Event::DocType(doctype) => break doctype,
+            Event::Start(_) | Event::Empty(_) | Event::Eof => return Ok(Cow::Borrowed(text)),
+            _ => {}
+        }
+    };
+
+    let mut text = text.to_string();
+    for (name, value) in parse_entities(str::from_utf8(&doctype)?) {
+        let reference = format!("&{name};");
+        let expanded_size =
+            text.len() as u64 + text.matches(&reference).count() as u64 * value.len() as u64;
+        if expanded_size > MAX_DECOMPRESSED_SIZE {
+            bail!("SVG is too large");
+        }
+        text = text.replace(&reference, &value);
+    }
+
+    Ok(Cow::Owned(text))
+}
+
+/// Renders an SVG at the size of the first `resize` or `fill` operation given in pixels, so it
+/// stays sharp instead of being scaled up from its intrinsic size.
+pub fn rasterize(data: &[u8], operations: &[Operation]) -> anyhow::Result<DynamicImage> {
+    let options = usvg::Options {
+        // Never read images referenced by path from the local file system
+        image_href_resolver: usvg::ImageHrefResolver {
+            resolve_string: Box::new(|_, _| None),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_data(&decompress(data)?, &options)?;
+
+    let size = tree.size();
+    let scale = render_scale(size.width(), size.height(), operations);
+    let scale = scale.min(MAX_SIZE / size.width().max(size.height()));
+    let width = (size.width() * scale).round().max(1.0) as u32;
+    let height = (size.height() * scale).round().max(1.0) as u32;
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or(anyhow!("Invalid SVG size"))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(
+            width as f32 / size.width(),
+            height as f32 / size.height(),
+        ),
+        &mut pixmap.as_mut(),
+    );
+
+    let pixels = pixmap.pixels();
+    Ok(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+        width,
+        height,
+        |x, y| {
+            let color = pixels[(y * width + x) as usize].demultiply();
+            Rgba([color.red(), color.green(), color.blue(), color.alpha()])
+        },
+    )))
+}
+
+/// Scale that makes the SVG match the first operation that sets its size. Operations that crop or
+/// rotate before that keep the intrinsic size.
+fn render_scale(width: f32, height: f32, operations: &[Operation]) -> f32 {
+    let scale = operations.iter().find_map(|operation| match *operation {
+        Operation::Resize(Length::Pixels(target_width), Length::Pixels(target_height)) => Some(
+            f32::min(target_width as f32 / width, target_height as f32 / height),
+        ),
+        Operation::Fill(Length::Pixels(target_width), Length::Pixels(target_height)) => Some(
+            f32::max(target_width as f32 / width, target_height as f32 / height),
+        ),
+        Operation::Resize(..)
+        | Operation::Fill(..)
+        | Operation::Crop(..)
+        | Operation::AspectRatio(..)
+        | Operation::Rotate(_)
+        | Operation::Border(..) => Some(1.0),
+        _ => None,
+    });
+
+    match scale {
+        Some(scale) if scale.is_finite() && scale > 0.0 => scale,
+        _ => 1.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use image::GenericImageView;
+    use std::io::Write;
+
+    const SVG: &[u8] = br##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <rect width="10" height="10" fill="#ff0000"/>
+    </svg>"##;
+
+    #[test]
+    fn test_rasterizes_at_intrinsic_size() {
+        let image = rasterize(SVG, &[]).unwrap();
+        assert_eq!(image.dimensions(), (20, 10));
+        assert_eq!(image.get_pixel(5, 5), Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(15, 5), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_rasterizes_at_target_size() {
+        let operations = [Operation::Resize(Length::Pixels(200), Length::Pixels(200))];
+        let image = rasterize(SVG, &operations).unwrap();
+        assert_eq!(image.dimensions(), (200, 100));
+        assert_eq!(image.get_pixel(99, 50), Rgba([255, 0, 0, 255]));
+
+        let operations = [Operation::Fill(Length::Pixels(40), Length::Pixels(40))];
+        let image = rasterize(SVG, &operations).unwrap();
+        assert_eq!(image.dimensions(), (80, 40));
+    }
+
+    #[test]
+    fn test_keeps_intrinsic_size_after_crop() {
+        let operations = [
+            Operation::Crop(Length::Pixels(10), Length::Pixels(10)),
+            Operation::Resize(Length::Pixels(200), Length::Pixels(200)),
+        ];
+        let image = rasterize(SVG, &operations).unwrap();
+        assert_eq!(image.dimensions(), (20, 10));
+    }
+
+    fn sanitize_str(svg: &str) -> String {
+        String::from_utf8(sanitize(svg.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_removes_scripts_and_handlers() {
+        let result = sanitize_str(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" onload="alert(1)">
+                <script>alert(2)</script>
+                <svg:script xmlns:svg="http://www.w3.org/2000/svg"/>
+                <foreignObject><iframe src="https://example.com"/></foreignObject>
+                <rect width="10" height="10" fill="red" onclick="alert(3)"/>
+            </svg>"#,
+        );
+        assert!(!result.contains("alert"));
+        assert!(!result.contains("iframe"));
+        assert!(result.contains(r#"<rect width="10" height="10" fill="red"/>"#));
+    }
+
+    #[test]
+    fn test_removes_external_references() {
+        let result = sanitize_str(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink">
+                <a xlink:href="javascript:alert(1)"><use href="#shape"/></a>
+                <image href="https://example.com/tracker.png"/>
+                <image href="data:image/png;base64,AAAA"/>
+                <rect fill="url(https://example.com/x.svg#p)" stroke="url(#gradient)"/>
+                <animate attributeName="xlink:href" to="javascript:alert(2)"/>
+                <style>@import url(https://example.com/x.css);</style>
+                <style><![CDATA[rect { fill: url('#gradient'); }]]></style>
+                <style>@&#105;mport "https://example.com/x.css";</style>
+                <style>rect{fill:url&#40;https://example.com/p.svg#a&#41;}</style>
+                <style>rect > circle { fill: url(&quot;#gradient&quot;); }</style>
+            </svg>"##,
+        );
+        assert!(!result.contains("javascript"));
+        assert!(!result.contains("example.com"));
+        assert!(result.contains(r##"<use href="#shape"/>"##));
+        assert!(result.contains("data:image/png"));
+        assert!(result.contains(r##"<rect stroke="url(#gradient)"/>"##));
+        assert!(result.contains("<style><![CDATA[rect"));
+        assert!(
+            result.contains(r#"<style>rect &gt; circle { fill: url(&quot;#gradient&quot;); }"#)
+        );
+        assert!(!result.contains("mport"));
+        assert!(!result.contains("&#"));
+    }
+
+    #[test]
+    fn test_removes_foreign_elements_and_attributes() {
+        let result = sanitize_str(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:x="http://www.w3.org/1999/xhtml">
+                <x:img src="https://example.com/tracker.png"/>
+                <x:iframe src="javascript:alert(1)"/>
+                <g><x:form action="https://example.com/form"><x:input/></x:form></g>
+                <rect x:onclick="alert(2)" data-url="https://example.com" width="10"/>
+                <animateTransform attributeName="transform" type="rotate" values="0;90"/>
+                <set attributeName="fill" to="javascript:alert(3)"/>
+            </svg>"#,
+        );
+        assert!(!result.contains("example.com"));
+        assert!(!result.contains("alert"));
+        assert!(!result.contains("xhtml"));
+        assert!(result.contains("<g></g>"));
+        assert!(result.contains(r#"<rect width="10"/>"#));
+        assert!(result.contains(r#"values="0;90""#));
+        assert!(result.contains(r#"<set attributeName="fill"/>"#));
+    }
+
+    #[test]
+    fn test_decompresses_svgz() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(br#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"/>"#)
+            .unwrap();
+        let data = encoder.finish().unwrap();
+
+        let result = String::from_utf8(sanitize(&data).unwrap()).unwrap();
+        assert!(result.starts_with("<svg"));
+        assert_eq!(rasterize(&data, &[]).unwrap().dimensions(), (20, 10));
+    }
+
+    #[test]
+    fn test_fails_on_svgz_bomb() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        let chunk = vec![b' '; 1024 * 1024];
+        for _ in 0..=MAX_DECOMPRESSED_SIZE / chunk.len() as u64 {
+            encoder.write_all(&chunk).unwrap();
+        }
+        let data = encoder.finish().unwrap();
+        assert!(sanitize(&data).is_err());
+    }
+
+    #[test]
+    fn test_fails_on_entity_bomb() {
+        let value = "a".repeat(100 * 1024);
+        let references = "&bomb;".repeat(10_000);
+        let svg = format!(
+            r#"<!DOCTYPE svg [<!ENTITY bomb "{value}">]><svg xmlns="http://www.w3.org/2000/svg"><text>{references}</text></svg>"#
+        );
+        assert!(sanitize(svg.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_resolves_internal_entities() {
+        let result = sanitize_str(
+            r#"<?xml version="1.0"?>
+            <!DOCTYPE svg [<!ENTITY ns_svg "http://www.w3.org/2000/svg"><!ENTITY ext SYSTEM "file:///etc/passwd">]>
+            <svg xmlns="&ns_svg;"><!-- comment --><text>&amp;</text></svg>"#,
+        );
+        assert!(result.starts_with(r#"<?xml version="1.0"?>"#));
+        assert!(!result.contains("DOCTYPE"));
+        assert!(!result.contains("comment"));
+        assert!(result.contains(r#"<svg xmlns="http://www.w3.org/2000/svg">"#));
+        assert!(result.contains("<text>&amp;</text>"));
+
+        assert!(
+            sanitize(
+                br#"<!DOCTYPE svg [<!ENTITY ext SYSTEM "file:///etc/passwd">]><svg>&ext;</svg>"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_rejects_non_svg_documents() {
+        assert!(sanitize(b"<html><svg/></html>").is_err());
+        assert!(sanitize(b"not xml").is_err());
+    }
+
+    #[test]
+    fn test_passes_through_without_raster_operations() {
+        assert!(is_passthrough(&[]));
+        assert!(is_passthrough(&[Operation::Quality(80)]));
+        assert!(!is_passthrough(&[Operation::Format(
+            image::ImageFormat::Png
+        )]));
+        assert!(!is_passthrough(&[Operation::Circle]));
+    }
+
+    #[test]
+    fn test_fails_on_invalid_svg() {
+        assert!(rasterize(b"<svg", &[]).is_err());
+    }
+}
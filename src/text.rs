@@ -0,0 +1,170 @@
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont, point};
+use anyhow::bail;
+use image::{Rgba, RgbaImage};
+use std::fs;
+use std::path::Path;
+
+/// Most characters drawn by a single text operation.
+pub const MAX_TEXT_LENGTH: usize = 1000;
+
+/// Largest width or height of rendered text.
+const MAX_CANVAS_SIZE: u32 = 8192;
+
+/// Fonts loaded from a directory, looked up by file name without the extension.
+#[derive(Default)]
+pub struct Fonts {
+    fonts: Vec<(String, FontArc)>,
+}
+
+impl Fonts {
+    pub fn load(directory: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut fonts = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let is_font = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    matches!(extension.to_ascii_lowercase().as_str(), "ttf" | "otf")
+                });
+            let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if is_font {
+                fonts.push((name.to_string(), FontArc::try_from_vec(fs::read(&path)?)?));
+            }
+        }
+        fonts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(Self { fonts })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fonts.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the named font, or the first one in alphabetical order if no name is given.
+    pub fn get(&self, name: Option<&str>) -> Option<&FontArc> {
+        match name {
+            Some(name) => self
+                .fonts
+                .iter()
+                .find(|(font_name, _)| font_name == name)
+                .map(|(_, font)| font),
+            None => self.fonts.first().map(|(_, font)| font),
+        }
+    }
+}
+
+/// Rasterizes text into a transparent image just large enough to hold it. Lines are separated by
+/// newlines and left-aligned. Fails if the text would be larger than allowed.
+pub fn render_text(
+    font: &FontArc,
+    text: &str,
+    size: f32,
+    color: Rgba<u8>,
+) -> anyhow::Result<RgbaImage> {
+    let font = font.as_scaled(PxScale::from(size));
+    let line_height = font.height() + font.line_gap();
+
+    let mut glyphs = Vec::new();
+    let mut width = 0.0f32;
+    let mut line_count = 0;
+    for (line_index, line) in text.lines().enumerate() {
+        let baseline = font.ascent() + line_index as f32 * line_height;
+        let mut caret = 0.0;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            glyphs.push(id.with_scale_and_position(font.scale(), point(caret, baseline)));
+            caret += font.h_advance(id);
+            previous = Some(id);
+        }
+        width = width.max(caret);
+        line_count = line_index + 1;
+    }
+
+    let height = line_count as f32 * line_height - font.line_gap();
+    let (width, height) = (width.ceil().max(1.0), height.ceil().max(1.0));
+    if width > MAX_CANVAS_SIZE as f32 || height > MAX_CANVAS_SIZE as f32 {
+        bail!("Text is too large");
+    }
+
+    let mut image = RgbaImage::new(width as u32, height as u32);
+    for glyph in glyphs {
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|x, y, coverage| {
+            let x = bounds.min.x as i64 + x as i64;
+            let y = bounds.min.y as i64 + y as i64;
+            if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+                return;
+            }
+
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            let alpha = (coverage.clamp(0.0, 1.0) * color.0[3] as f32).round() as u8;
+            if alpha > pixel.0[3] {
+                *pixel = Rgba([color.0[0], color.0[1], color.0[2], alpha]);
+            }
+        });
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_test_font() -> FontArc {
+        FontArc::try_from_slice(include_bytes!("../tests/fixtures/Tuffy.ttf")).unwrap()
+    }
+
+    #[test]
+    fn test_renders_text() {
+        let font = load_test_font();
+        let color = Rgba([255, 0, 0, 255]);
+        let image = render_text(&font, "Hi", 32.0, color).unwrap();
+        assert!(image.width() > 10 && image.width() < 64);
+        assert!(image.height() >= 32 && image.height() < 48);
+        assert!(image.pixels().any(|pixel| *pixel == color));
+        assert!(image.pixels().any(|pixel| pixel.0[3] == 0));
+
+        let two_lines = render_text(&font, "Hi\nHi", 32.0, color).unwrap();
+        assert_eq!(two_lines.width(), image.width());
+        assert!(two_lines.height() > image.height() * 3 / 2);
+    }
+
+    #[test]
+    fn test_fails_rendering_oversized_text() {
+        let font = load_test_font();
+        let text = "W".repeat(MAX_TEXT_LENGTH);
+        assert!(render_text(&font, &text, 1000.0, Rgba([255; 4])).is_err());
+        let text = "W\n".repeat(MAX_TEXT_LENGTH / 2);
+        assert!(render_text(&font, &text, 100.0, Rgba([255; 4])).is_err());
+    }
+
+    #[test]
+    fn test_fails_loading_missing_directory() {
+        let result = Fonts::load("/nonexistent/fonts");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_ignores_files_that_are_not_fonts() {
+        let directory = std::env::temp_dir().join(format!("pinchrs-fonts-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("readme.txt"), "not a font").unwrap();
+
+        let fonts = Fonts::load(&directory).unwrap();
+        assert_eq!(fonts.names().count(), 0);
+        assert!(fonts.get(None).is_none());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::bail;
+use image::{ColorType, DynamicImage, Rgba};
+
+pub fn parse_hex_color(hex: &str) -> anyhow::Result<Rgba<u8>> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if !hex.is_ascii() {
+        bail!("Invalid color");
+    }
+
+    let expanded: String = match hex.len() {
+        3 | 4 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 | 8 => hex.to_string(),
+        _ => {
+            bail!("Invalid color");
+        }
+    };
+
+    let mut channels = [255u8; 4];
+    for (i, channel) in channels.iter_mut().enumerate().take(expanded.len() / 2) {
+        *channel = u8::from_str_radix(&expanded[i * 2..i * 2 + 2], 16)?;
+    }
+
+    Ok(Rgba(channels))
+}
+
+pub fn convert_color_type(image: DynamicImage, color: ColorType) -> DynamicImage {
+    if image.color() == color {
+        return image;
+    }
+
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(image.into_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(image.into_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(image.into_rgb8()),
+        ColorType::Rgba8 => DynamicImage::ImageRgba8(image.into_rgba8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(image.into_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(image.into_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(image.into_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(image.into_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(image.into_rgb32f()),
+        ColorType::Rgba32F => DynamicImage::ImageRgba32F(image.into_rgba32f()),
+        _ => image,
+    }
+}
+
+/// Returns the color type with an alpha channel added, keeping the bit depth.
+pub fn with_alpha(color: ColorType) -> ColorType {
+    match color {
+        ColorType::L8 => ColorType::La8,
+        ColorType::Rgb8 => ColorType::Rgba8,
+        ColorType::L16 => ColorType::La16,
+        ColorType::Rgb16 => ColorType::Rgba16,
+        ColorType::Rgb32F => ColorType::Rgba32F,
+        color => color,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_color() {
+        assert_eq!(parse_hex_color("ff8000").unwrap(), Rgba([255, 128, 0, 255]));
+        assert_eq!(parse_hex_color("#f80").unwrap(), Rgba([255, 136, 0, 255]));
+        assert_eq!(
+            parse_hex_color("ff800080").unwrap(),
+            Rgba([255, 128, 0, 128])
+        );
+        assert_eq!(parse_hex_color("f808").unwrap(), Rgba([255, 136, 0, 136]));
+    }
+
+    #[test]
+    fn fails_parsing_invalid_hex_color() {
+        assert!(parse_hex_color("ff800").is_err());
+        assert!(parse_hex_color("gggggg").is_err());
+        assert!(parse_hex_color("ffé").is_err());
+    }
+}
@@ -2,12 +2,25 @@ use axum::http::HeaderValue;
 use image::ImageFormat;
 use std::path::Path;
 
-pub fn parse_image_format_from_content_type(content_type: &HeaderValue) -> Option<ImageFormat> {
+/// Format of a source image, which is either decoded by `image`, rasterized from SVG or decoded
+/// from JPEG XL or PCX.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    Image(ImageFormat),
+    Svg,
+    Jxl,
+    Pcx,
+}
+
+pub fn parse_image_format_from_content_type(content_type: &HeaderValue) -> Option<InputFormat> {
     let Ok(content_type) = content_type.to_str() else {
         return None;
     };
 
-    match content_type.to_ascii_lowercase().as_str() {
+    let format = match content_type.to_ascii_lowercase().as_str() {
+        "image/svg+xml" => return Some(InputFormat::Svg),
+        "image/jxl" => return Some(InputFormat::Jxl),
+        "image/x-pcx" | "image/vnd.zbrush.pcx" => return Some(InputFormat::Pcx),
         "image/png" => Some(ImageFormat::Png),
         "image/jpeg" | "image/jpg" => Some(ImageFormat::Jpeg),
         "image/gif" => Some(ImageFormat::Gif),
@@ -23,16 +36,44 @@ pub fn parse_image_format_from_content_type(content_type: &HeaderValue) -> Optio
         "image/farbfeld" => Some(ImageFormat::Farbfeld),
         "image/avif" => Some(ImageFormat::Avif),
         "image/qoi" => Some(ImageFormat::Qoi),
-        "image/x-pcx" => Some(ImageFormat::Pcx),
         _ => None,
-    }
+    };
+    format.map(InputFormat::Image)
 }
 
-pub fn parse_image_format_from_filename(filename: &str) -> Option<ImageFormat> {
+pub fn parse_image_format_from_filename(filename: &str) -> Option<InputFormat> {
     let extension: &str = Path::new(filename)
         .extension()
         .and_then(|extension| extension.to_str())?;
-    ImageFormat::from_extension(extension)
+    if extension.eq_ignore_ascii_case("svg") || extension.eq_ignore_ascii_case("svgz") {
+        return Some(InputFormat::Svg);
+    }
+    if extension.eq_ignore_ascii_case("jxl") {
+        return Some(InputFormat::Jxl);
+    }
+    if extension.eq_ignore_ascii_case("pcx") {
+        return Some(InputFormat::Pcx);
+    }
+    ImageFormat::from_extension(extension).map(InputFormat::Image)
+}
+
+/// Detects SVG documents, which have no magic bytes, by looking for an `<svg` tag near the start.
+pub fn is_svg(bytes: &[u8]) -> bool {
+    let start = &bytes[..bytes.len().min(4096)];
+    start.windows(4).any(|window| window == b"<svg")
+}
+
+/// Detects JPEG XL images from the signature of either a bare codestream or the container format.
+pub fn is_jxl(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0xff, 0x0a])
+        || bytes.starts_with(&[
+            0, 0, 0, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+        ])
+}
+
+/// Detects PCX images from the manufacturer, version and encoding bytes of the header.
+pub fn is_pcx(bytes: &[u8]) -> bool {
+    bytes.len() >= 128 && bytes[0] == 0x0a && [0, 2, 3, 4, 5].contains(&bytes[1]) && bytes[2] == 1
 }
 
 pub fn resolve_content_type(format: ImageFormat) -> &'static str {
@@ -52,7 +93,6 @@ pub fn resolve_content_type(format: ImageFormat) -> &'static str {
         ImageFormat::Farbfeld => "image/farbfeld",
         ImageFormat::Avif => "image/avif",
         ImageFormat::Qoi => "image/qoi",
-        ImageFormat::Pcx => "image/x-pcx",
         _ => "application/octet-stream",
     }
 }
@@ -65,7 +105,19 @@ mod tests {
     fn parses_from_content_type() {
         let header = HeaderValue::from_str("image/avif").unwrap();
         let result = parse_image_format_from_content_type(&header);
-        assert_eq!(result, Some(ImageFormat::Avif));
+        assert_eq!(result, Some(InputFormat::Image(ImageFormat::Avif)));
+
+        let header = HeaderValue::from_str("image/svg+xml").unwrap();
+        let result = parse_image_format_from_content_type(&header);
+        assert_eq!(result, Some(InputFormat::Svg));
+
+        let header = HeaderValue::from_str("image/jxl").unwrap();
+        let result = parse_image_format_from_content_type(&header);
+        assert_eq!(result, Some(InputFormat::Jxl));
+
+        let header = HeaderValue::from_str("image/x-pcx").unwrap();
+        let result = parse_image_format_from_content_type(&header);
+        assert_eq!(result, Some(InputFormat::Pcx));
 
         let header = HeaderValue::from_str("foo/bar").unwrap();
         let result = parse_image_format_from_content_type(&header);
@@ -75,12 +127,45 @@ mod tests {
     #[test]
     fn parses_from_filename() {
         let result = parse_image_format_from_filename("image.jpg");
-        assert_eq!(result, Some(ImageFormat::Jpeg));
+        assert_eq!(result, Some(InputFormat::Image(ImageFormat::Jpeg)));
+
+        let result = parse_image_format_from_filename("logo.SVG");
+        assert_eq!(result, Some(InputFormat::Svg));
+
+        let result = parse_image_format_from_filename("photo.jxl");
+        assert_eq!(result, Some(InputFormat::Jxl));
+
+        let result = parse_image_format_from_filename("scan.PCX");
+        assert_eq!(result, Some(InputFormat::Pcx));
 
         let result = parse_image_format_from_filename("foo.bar");
         assert_eq!(result, None);
     }
 
+    #[test]
+    fn detects_svg() {
+        assert!(is_svg(
+            b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>"
+        ));
+        assert!(!is_svg(b"\x89PNG\r\n\x1a\n"));
+    }
+
+    #[test]
+    fn detects_jxl() {
+        assert!(is_jxl(b"\xff\x0a\xfa\x7f"));
+        assert!(is_jxl(b"\0\0\0\x0cJXL \r\n\x87\n\0\0\0\x14ftypjxl "));
+        assert!(!is_jxl(b"\xff\xd8\xff\xe0"));
+    }
+
+    #[test]
+    fn detects_pcx() {
+        let mut header = vec![0; 128];
+        header[..3].copy_from_slice(&[0x0a, 5, 1]);
+        assert!(is_pcx(&header));
+        assert!(!is_pcx(&header[..64]));
+        assert!(!is_pcx(b"\x89PNG\r\n\x1a\n"));
+    }
+
     #[test]
     fn resolves_content_type() {
         let result = resolve_content_type(ImageFormat::Jpeg);
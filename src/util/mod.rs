@@ -1,2 +1,3 @@
+pub mod color;
 pub mod error;
 pub mod format;